@@ -1,38 +1,735 @@
-use std::collections::HashSet;
+use std::{
+    cmp::{max, min},
+    fmt,
+    str::FromStr,
+};
 
-fn main() {
-    let contents = std::fs::read_to_string("inputs/day4.txt")
-        .expect("Missing file!")
+use anyhow::Result;
+
+/// The highest section ID accepted, so that the length of any interval and
+/// the point just past its end both still fit in a `u64`.
+const MAX_SECTION: u64 = u64::MAX - 1;
+
+/// An inclusive range of section IDs, kept as its two endpoints so that
+/// membership tests never have to materialise the sections themselves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+struct Interval {
+    start: u64,
+    end: u64,
+}
+
+impl Interval {
+    fn new(start: u64, end: u64) -> Self {
+        Self { start, end }
+    }
+
+    fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    /// Whether `other` lies entirely within `self`, i.e. adding it to `self`
+    /// changes nothing.
+    fn contains(&self, other: &Interval) -> bool {
+        self.union(other) == Some(*self)
+    }
+
+    fn overlaps(&self, other: &Interval) -> bool {
+        self.start <= other.end && other.start <= self.end
+    }
+
+    fn intersection(&self, other: &Interval) -> Option<Interval> {
+        if !self.overlaps(other) {
+            return None;
+        }
+
+        Some(Interval::new(
+            max(self.start, other.start),
+            min(self.end, other.end),
+        ))
+    }
+
+    /// The single interval covering both, if they overlap or touch.
+    /// Disjoint intervals with a gap between them have no union.
+    fn union(&self, other: &Interval) -> Option<Interval> {
+        let (first, second) = if self.start <= other.start {
+            (self, other)
+        } else {
+            (other, self)
+        };
+
+        if first.end.saturating_add(1) < second.start {
+            return None;
+        }
+
+        Some(Interval::new(first.start, max(first.end, second.end)))
+    }
+}
+
+/// A normalized union of intervals: sorted, and with any overlapping or
+/// touching members merged together.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct IntervalSet {
+    intervals: Vec<Interval>,
+}
+
+impl IntervalSet {
+    /// Join overlapping or touching neighbours in one pass over the
+    /// intervals, which must already be sorted by start.
+    fn merge(&mut self) {
+        let mut merged: Vec<Interval> = Vec::with_capacity(self.intervals.len());
+
+        for current in self.intervals.drain(..) {
+            match merged.last_mut().and_then(|last| last.union(&current)) {
+                Some(joined) => *merged.last_mut().unwrap() = joined,
+                None => merged.push(current),
+            }
+        }
+
+        self.intervals = merged;
+    }
+
+    /// Total number of sections in the set.
+    fn sections(&self) -> u64 {
+        self.intervals.iter().map(Interval::len).sum()
+    }
+
+    /// The holes between the first and last section of the set.
+    fn gaps(&self) -> IntervalSet {
+        self.intervals
+            .windows(2)
+            .map(|pair| Interval::new(pair[0].end + 1, pair[1].start - 1))
+            .collect()
+    }
+}
+
+impl FromIterator<Interval> for IntervalSet {
+    fn from_iter<T: IntoIterator<Item = Interval>>(iter: T) -> Self {
+        let mut set = IntervalSet {
+            intervals: iter.into_iter().collect(),
+        };
+        set.intervals.sort_unstable();
+        set.merge();
+
+        set
+    }
+}
+
+/// How many assignments cover each section, stored as maximal runs of
+/// constant depth so it stays proportional to the number of assignments.
+#[derive(Debug, Clone)]
+struct Coverage {
+    segments: Vec<(Interval, usize)>,
+}
+
+impl Coverage {
+    fn new<'a>(assignments: impl IntoIterator<Item = &'a Interval>) -> Self {
+        // Sweep over +1 at each start and -1 just past each end, which
+        // MAX_SECTION leaves room for
+        let mut events: Vec<(u64, i64)> = assignments
+            .into_iter()
+            .flat_map(|a| [(a.start, 1), (a.end + 1, -1)])
+            .collect();
+        events.sort_unstable();
+
+        let mut segments = Vec::new();
+        let mut depth = 0i64;
+
+        for (i, &(pos, delta)) in events.iter().enumerate() {
+            depth += delta;
+
+            let next = match events.get(i + 1) {
+                Some(&(next, _)) => next,
+                None => break,
+            };
+
+            if depth > 0 && next > pos {
+                segments.push((Interval::new(pos, next - 1), depth as usize));
+            }
+        }
+
+        Self { segments }
+    }
+
+    fn covered(&self) -> IntervalSet {
+        self.covered_by_more_than(0)
+    }
+
+    fn covered_by_more_than(&self, k: usize) -> IntervalSet {
+        self.segments
+            .iter()
+            .filter(|(_, depth)| *depth > k)
+            .map(|(interval, _)| *interval)
+            .collect()
+    }
+
+    /// The deepest overlap and the first run of sections where it occurs.
+    fn max_depth(&self) -> Option<(usize, Interval)> {
+        let deepest = self.segments.iter().map(|(_, depth)| *depth).max()?;
+
+        self.covered_by_more_than(deepest - 1)
+            .intervals
+            .first()
+            .map(|interval| (deepest, *interval))
+    }
+}
+
+/// What to do with a range written back to front, like `7-3`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Reversed {
+    Reject,
+    Normalize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum AssignmentError {
+    NotANumber(String),
+    MissingDash(String),
+    MissingComma,
+    ReversedRange { start: u64, end: u64 },
+    SectionTooLarge(u64),
+}
+
+impl fmt::Display for AssignmentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotANumber(s) => write!(f, "'{}' is not a section number", s),
+            Self::MissingDash(s) => write!(f, "range '{}' is missing a '-'", s),
+            Self::MissingComma => write!(f, "expected at least two ranges separated by ','"),
+            Self::ReversedRange { start, end } => {
+                write!(f, "range {}-{} ends before it starts", start, end)
+            }
+            Self::SectionTooLarge(section) => write!(
+                f,
+                "section {} is past the highest supported section, {}",
+                section, MAX_SECTION
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AssignmentError {}
+
+/// An [`AssignmentError`] along with the (1-based) line it occurred on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct LineError {
+    line: usize,
+    error: AssignmentError,
+}
+
+impl fmt::Display for LineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.error)
+    }
+}
+
+impl std::error::Error for LineError {}
+
+impl Interval {
+    fn parse(s: &str, reversed: Reversed) -> Result<Self, AssignmentError> {
+        let (start, end) = s
+            .split_once('-')
+            .ok_or_else(|| AssignmentError::MissingDash(s.to_owned()))?;
+
+        let parse_endpoint = |x: &str| match x.parse::<u64>() {
+            Ok(section) if section > MAX_SECTION => Err(AssignmentError::SectionTooLarge(section)),
+            Ok(section) => Ok(section),
+            Err(_) => Err(AssignmentError::NotANumber(x.to_owned())),
+        };
+        let (start, end) = (parse_endpoint(start)?, parse_endpoint(end)?);
+
+        match reversed {
+            _ if start <= end => Ok(Interval::new(start, end)),
+            Reversed::Normalize => Ok(Interval::new(end, start)),
+            Reversed::Reject => Err(AssignmentError::ReversedRange { start, end }),
+        }
+    }
+}
+
+impl FromStr for Interval {
+    type Err = AssignmentError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Interval::parse(s, Reversed::Reject)
+    }
+}
+
+/// The assignments of one line, for however many elves share it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Group {
+    members: Vec<Interval>,
+}
+
+impl Group {
+    fn parse(line: &str, reversed: Reversed) -> Result<Self, AssignmentError> {
+        if !line.contains(',') {
+            return Err(AssignmentError::MissingComma);
+        }
+
+        let members = line
+            .split(',')
+            .map(|range| Interval::parse(range, reversed))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Group { members })
+    }
+
+    /// Whether member `i` sits above member `j` in the containment order.
+    /// Identical assignments are ordered by position so the order stays strict.
+    fn above(&self, i: usize, j: usize) -> bool {
+        let (a, b) = (&self.members[i], &self.members[j]);
+
+        i != j && a.contains(b) && (a != b || i < j)
+    }
+
+    /// The covering pairs `(outer, inner)` of the containment order, i.e.
+    /// the edges of its Hasse diagram: `outer` contains `inner` with no other
+    /// member nested between them.
+    fn containments(&self) -> Vec<(usize, usize)> {
+        let n = self.members.len();
+        let mut edges = Vec::new();
+
+        for i in 0..n {
+            for j in 0..n {
+                if self.above(i, j) && !(0..n).any(|k| self.above(i, k) && self.above(k, j)) {
+                    edges.push((i, j));
+                }
+            }
+        }
+
+        edges
+    }
+
+    /// Every pair of members sharing at least one section, with what they share.
+    fn overlaps(&self) -> Vec<(usize, usize, Interval)> {
+        let n = self.members.len();
+
+        (0..n)
+            .flat_map(|i| (i + 1..n).map(move |j| (i, j)))
+            .filter_map(|(i, j)| {
+                self.members[i]
+                    .intersection(&self.members[j])
+                    .map(|shared| (i, j, shared))
+            })
+            .collect()
+    }
+
+    /// The sections every member of the group is assigned to.
+    fn common(&self) -> Option<Interval> {
+        let (first, rest) = self.members.split_first()?;
+
+        rest.iter()
+            .try_fold(*first, |acc, member| acc.intersection(member))
+    }
+
+    /// Total sections shared, summed over every overlapping pair. Each pair
+    /// can share up to `u64::MAX` sections, so the total needs more room.
+    fn overlap_sections(&self) -> u128 {
+        self.overlaps()
+            .iter()
+            .map(|(_, _, shared)| u128::from(shared.len()))
+            .sum()
+    }
+
+    /// The same members laid end to end from the group's first section, in
+    /// their original order of appearance along the line. Lengths are kept and
+    /// nothing overlaps, so the total overlap drops to zero. `None` if the
+    /// members don't fit between that section and [`MAX_SECTION`].
+    fn packed(&self) -> Option<Group> {
+        let mut order: Vec<usize> = (0..self.members.len()).collect();
+        order.sort_by_key(|&i| (self.members[i].start, i));
+
+        let mut members = self.members.clone();
+        let mut cursor = order.first().map_or(0, |&i| self.members[i].start);
+
+        for i in order {
+            let end = cursor
+                .checked_add(self.members[i].len() - 1)
+                .filter(|&end| end <= MAX_SECTION)?;
+            members[i] = Interval::new(cursor, end);
+            cursor = end + 1;
+        }
+
+        Some(Group { members })
+    }
+}
+
+impl FromStr for Group {
+    type Err = AssignmentError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Group::parse(s, Reversed::Reject)
+    }
+}
+
+/// A proposed move of one elf's assignment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Reassignment {
+    group: usize,
+    member: usize,
+    from: Interval,
+    to: Interval,
+}
+
+#[derive(Debug, Clone)]
+struct Rebalance {
+    groups: Vec<Group>,
+    moves: Vec<Reassignment>,
+    /// Groups with a containment that had no room to be repacked.
+    unpacked: Vec<usize>,
+}
+
+/// Repack every group that has a full containment so that no two of its
+/// members overlap. Groups without a containment, or without the room to
+/// repack, are left as they are.
+fn rebalance(groups: &[Group]) -> Rebalance {
+    let mut moves = Vec::new();
+    let mut unpacked = Vec::new();
+
+    let groups = groups
+        .iter()
+        .enumerate()
+        .map(|(g, group)| {
+            if group.containments().is_empty() {
+                return group.clone();
+            }
+
+            let Some(packed) = group.packed() else {
+                unpacked.push(g);
+                return group.clone();
+            };
+
+            for (member, (from, to)) in group.members.iter().zip(&packed.members).enumerate() {
+                if from != to {
+                    moves.push(Reassignment {
+                        group: g,
+                        member,
+                        from: *from,
+                        to: *to,
+                    });
+                }
+            }
+
+            packed
+        })
+        .collect();
+
+    Rebalance {
+        groups,
+        moves,
+        unpacked,
+    }
+}
+
+fn parse_groups(input: &str, reversed: Reversed) -> Result<Vec<Group>, LineError> {
+    input
         .lines()
-        .map(|line| {
-            let ranges = line
-                .split(",")
-                .take(2)
-                .map(|range| {
-                    let endpoints = range
-                        .split("-")
-                        .take(2)
-                        .map(|x| x.parse::<u32>().unwrap())
-                        .collect::<Vec<u32>>();
-
-                    (endpoints[0]..=endpoints[1]).collect::<HashSet<_>>()
-                })
-                .collect::<Vec<HashSet<_>>>();
-            ranges
+        .enumerate()
+        .map(|(i, line)| {
+            Group::parse(line, reversed).map_err(|error| LineError { line: i + 1, error })
         })
-        .collect::<Vec<_>>();
+        .collect()
+}
 
-    let part1 = contents
+fn count_contained(groups: &[Group]) -> usize {
+    groups
         .iter()
-        .filter(|ranges| ranges[0].is_subset(&ranges[1]) || ranges[1].is_subset(&ranges[0]))
-        .count();
-
-    println!("Part 1: {}", part1);
+        .filter(|group| !group.containments().is_empty())
+        .count()
+}
 
-    let part2 = contents
+fn count_overlapping(groups: &[Group]) -> usize {
+    groups
         .iter()
-        .filter(|ranges| ranges[0].intersection(&ranges[1]).count() > 0)
-        .count();
+        .filter(|group| !group.overlaps().is_empty())
+        .count()
+}
+
+fn main() -> Result<()> {
+    let reversed = if std::env::args().any(|arg| arg == "--normalize") {
+        Reversed::Normalize
+    } else {
+        Reversed::Reject
+    };
+    let contents = parse_groups(&std::fs::read_to_string("inputs/day4.txt")?, reversed)?;
+
+    println!("Part 1: {}", count_contained(&contents));
+    println!("Part 2: {}", count_overlapping(&contents));
+
+    println!(
+        "Groups sharing a common section: {}",
+        contents
+            .iter()
+            .filter(|group| group.common().is_some())
+            .count()
+    );
+
+    let coverage = Coverage::new(contents.iter().flat_map(|group| &group.members));
+    let covered = coverage.covered();
+
+    println!("Sections covered: {}", covered.sections());
+    println!("Gaps: {:?}", covered.gaps().intervals);
+    println!(
+        "Sections covered by more than 2 elves: {}",
+        coverage.covered_by_more_than(2).sections()
+    );
+    println!("Max overlap depth: {:?}", coverage.max_depth());
+
+    let plan = rebalance(&contents);
+
+    println!(
+        "Rebalancing moves {} elves, overlap {} -> {} sections",
+        plan.moves.len(),
+        contents.iter().map(Group::overlap_sections).sum::<u128>(),
+        plan.groups
+            .iter()
+            .map(Group::overlap_sections)
+            .sum::<u128>()
+    );
+    if !plan.unpacked.is_empty() {
+        println!("Groups with no room to rebalance: {:?}", plan.unpacked);
+    }
+    println!(
+        "Part 1 after rebalancing: {}",
+        count_contained(&plan.groups)
+    );
+    println!(
+        "Part 2 after rebalancing: {}",
+        count_overlapping(&plan.groups)
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use crate::{
+        count_contained, count_overlapping, parse_groups, rebalance, AssignmentError, Coverage,
+        Group, Interval, IntervalSet, LineError, Reassignment, Reversed,
+    };
+
+    #[test]
+    fn test_one() -> Result<()> {
+        let groups = parse_groups(
+            &std::fs::read_to_string("inputs/day4.test")?,
+            Reversed::Reject,
+        )?;
+
+        assert_eq!(count_contained(&groups), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_two() -> Result<()> {
+        let groups = parse_groups(
+            &std::fs::read_to_string("inputs/day4.test")?,
+            Reversed::Reject,
+        )?;
+
+        assert_eq!(count_overlapping(&groups), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_interval_ops() {
+        let a = Interval::new(2, 6);
+        let b = Interval::new(4, 8);
+        let c = Interval::new(7, 9);
+
+        assert_eq!(a.intersection(&b), Some(Interval::new(4, 6)));
+        assert_eq!(a.intersection(&c), None);
+        assert_eq!(a.union(&b), Some(Interval::new(2, 8)));
+        assert_eq!(a.union(&c), Some(Interval::new(2, 9)));
+        assert_eq!(a.union(&Interval::new(8, 9)), None);
+        assert!(Interval::new(3, 7).contains(&Interval::new(3, 3)));
+    }
+
+    #[test]
+    fn test_large_ranges() -> Result<()> {
+        let groups = parse_groups(
+            "1-4000000000,2-3999999999\n1-2,4000000000-4000000001",
+            Reversed::Reject,
+        )?;
+
+        assert_eq!(count_contained(&groups), 1);
+        assert_eq!(count_overlapping(&groups), 1);
+        assert_eq!(groups[0].members[0].len(), 4_000_000_000);
+
+        // The widest assignment there is still has a length and an end
+        let groups = parse_groups(
+            "0-18446744073709551614,0-18446744073709551614",
+            Reversed::Reject,
+        )?;
+        assert_eq!(groups[0].members[0].len(), u64::MAX);
+        assert_eq!(
+            Coverage::new(&groups[0].members).covered().sections(),
+            u64::MAX
+        );
+
+        // ... but two of them can't be laid end to end
+        let plan = rebalance(&groups);
+        assert_eq!(plan.unpacked, vec![0]);
+        assert_eq!(plan.groups, groups);
+
+        // Three of them share every section pairwise, more than a u64 holds
+        let groups = parse_groups(
+            "0-18446744073709551614,0-18446744073709551614,0-18446744073709551614",
+            Reversed::Reject,
+        )?;
+        assert_eq!(groups[0].overlap_sections(), 3 * u128::from(u64::MAX));
+
+        assert_eq!(
+            "0-18446744073709551615".parse::<Interval>(),
+            Err(AssignmentError::SectionTooLarge(u64::MAX))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_interval_set() {
+        let set: IntervalSet = [
+            Interval::new(10, 12),
+            Interval::new(1, 3),
+            Interval::new(4, 5),
+            Interval::new(11, 20),
+            Interval::new(8, 8),
+        ]
+        .into_iter()
+        .collect();
+
+        assert_eq!(
+            set.intervals,
+            vec![
+                Interval::new(1, 5),
+                Interval::new(8, 8),
+                Interval::new(10, 20)
+            ]
+        );
+        assert_eq!(set.sections(), 17);
+        assert_eq!(
+            set.gaps().intervals,
+            vec![Interval::new(6, 7), Interval::new(9, 9)]
+        );
+    }
+
+    #[test]
+    fn test_coverage() -> Result<()> {
+        let groups = parse_groups(
+            &std::fs::read_to_string("inputs/day4.test")?,
+            Reversed::Reject,
+        )?;
+        let coverage = Coverage::new(groups.iter().flat_map(|group| &group.members));
+
+        assert_eq!(coverage.covered().intervals, vec![Interval::new(2, 9)]);
+        assert!(coverage.covered().gaps().intervals.is_empty());
+        assert_eq!(
+            coverage.covered_by_more_than(4).intervals,
+            vec![Interval::new(3, 7)]
+        );
+        assert_eq!(coverage.max_depth(), Some((8, Interval::new(6, 6))));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_groups() -> Result<()> {
+        let groups = parse_groups(
+            "1-10,2-5,3-4,8-9,20-30\n2-6,4-8,5-9\n3-3,3-3",
+            Reversed::Reject,
+        )?;
+
+        assert_eq!(groups[0].members.len(), 5);
+        assert_eq!(groups[0].containments(), vec![(0, 1), (0, 3), (1, 2)]);
+        assert_eq!(
+            groups[0].overlaps(),
+            vec![
+                (0, 1, Interval::new(2, 5)),
+                (0, 2, Interval::new(3, 4)),
+                (0, 3, Interval::new(8, 9)),
+                (1, 2, Interval::new(3, 4)),
+            ]
+        );
+        assert_eq!(groups[0].common(), None);
+
+        assert!(groups[1].containments().is_empty());
+        assert_eq!(groups[1].overlaps().len(), 3);
+        assert_eq!(groups[1].common(), Some(Interval::new(5, 6)));
+
+        assert_eq!(groups[2].containments(), vec![(0, 1)]);
+        assert_eq!(groups[2].common(), Some(Interval::new(3, 3)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rebalance() -> Result<()> {
+        let groups = parse_groups(
+            &std::fs::read_to_string("inputs/day4.test")?,
+            Reversed::Reject,
+        )?;
+        let plan = rebalance(&groups);
+
+        assert_eq!(
+            plan.moves,
+            vec![
+                Reassignment {
+                    group: 3,
+                    member: 1,
+                    from: Interval::new(3, 7),
+                    to: Interval::new(9, 13),
+                },
+                Reassignment {
+                    group: 4,
+                    member: 0,
+                    from: Interval::new(6, 6),
+                    to: Interval::new(7, 7),
+                },
+            ]
+        );
+
+        assert_eq!(count_contained(&plan.groups), 0);
+        assert_eq!(count_overlapping(&plan.groups), 2);
+        assert!(plan.groups.iter().zip(&groups).all(|(new, old)| new
+            .members
+            .iter()
+            .zip(&old.members)
+            .all(|(a, b)| a.len() == b.len())));
+        assert_eq!(
+            plan.groups
+                .iter()
+                .map(Group::overlap_sections)
+                .sum::<u128>(),
+            4
+        );
+
+        Ok(())
+    }
 
-    println!("Part 2: {}", part2);
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(
+            "2-x".parse::<Interval>(),
+            Err(AssignmentError::NotANumber("x".to_owned()))
+        );
+        assert_eq!(
+            "2-4,68".parse::<Group>(),
+            Err(AssignmentError::MissingDash("68".to_owned()))
+        );
+        assert_eq!("2-4".parse::<Group>(), Err(AssignmentError::MissingComma));
+        assert_eq!(
+            parse_groups("2-4,6-8\n7-3,1-2", Reversed::Reject),
+            Err(LineError {
+                line: 2,
+                error: AssignmentError::ReversedRange { start: 7, end: 3 },
+            })
+        );
+        assert_eq!(
+            parse_groups("2-4,6-8\n7-3,1-2", Reversed::Normalize).map(|groups| groups[1].clone()),
+            Ok(Group {
+                members: vec![Interval::new(3, 7), Interval::new(1, 2)],
+            })
+        );
+    }
 }
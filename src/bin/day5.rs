@@ -1,33 +1,476 @@
+#![cfg_attr(test, feature(test))]
+
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{HashMap, HashSet, VecDeque},
+    fmt,
+    io::{BufRead, Write},
+    path::Path,
     str::FromStr,
-    usize,
+    sync::LazyLock,
 };
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Result};
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 
-type Stack<T> = VecDeque<T>;
+/// A column of crates, bottom first, so the top crate is always at the end.
+type Stack<T> = Vec<T>;
 
-#[derive(Debug, Clone)]
+/// What is written on a crate, which is not always a single letter.
+type Label = String;
+
+/// Accepted spellings of a move, each capturing the amount, source and
+/// target in that order.
+static MOVE_PATTERNS: LazyLock<[Regex; 2]> = LazyLock::new(|| {
+    [
+        Regex::new(r"^move\s+(\d+)\s+from\s+(\d+)\s+to\s+(\d+)$").unwrap(),
+        Regex::new(r"^(\d+)\s+(\d+)\s*->\s*(\d+)$").unwrap(),
+    ]
+});
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct Instruction {
     amount: usize,
     from: usize,
     to: usize,
+    /// Line of the input the instruction was read from.
+    #[serde(default)]
+    line: usize,
+}
+
+/// Why an instruction cannot be carried out on the stacks it will meet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum InvalidMove {
+    MissingColumn {
+        line: usize,
+        column: usize,
+    },
+    NotEnoughCrates {
+        line: usize,
+        requested: usize,
+        available: usize,
+    },
+    Overflow {
+        line: usize,
+        column: usize,
+        capacity: usize,
+    },
+    /// A column is already past the capacity limit before any move.
+    OverCapacity {
+        column: usize,
+        height: usize,
+        capacity: usize,
+    },
+}
+
+impl fmt::Display for InvalidMove {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingColumn { line, column } => {
+                write!(f, "line {}: there is no column {}", line, column)
+            }
+            Self::NotEnoughCrates {
+                line,
+                requested,
+                available,
+            } => write!(
+                f,
+                "line {}: asked to move {} crates but only {} are there",
+                line, requested, available
+            ),
+            Self::Overflow {
+                line,
+                column,
+                capacity,
+            } => write!(
+                f,
+                "line {}: no room past column {}, which holds at most {} crates",
+                line, column, capacity
+            ),
+            Self::OverCapacity {
+                column,
+                height,
+                capacity,
+            } => write!(
+                f,
+                "column {} starts {} crates high, past its capacity of {}",
+                column, height, capacity
+            ),
+        }
+    }
+}
+
+impl std::error::Error for InvalidMove {}
+
+/// What to do with crates that would stack a column past its capacity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum OverflowPolicy {
+    /// Treat the instruction as invalid.
+    Error,
+    /// Set the crates that don't fit down on the following columns.
+    Spill,
+    /// Skip the instruction altogether.
+    Refuse,
+}
+
+impl FromStr for OverflowPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(Self::Error),
+            "spill" => Ok(Self::Spill),
+            "refuse" => Ok(Self::Refuse),
+            _ => Err(anyhow!("Unknown overflow policy '{}'", s)),
+        }
+    }
+}
+
+/// The tallest any column may get, as in a real yard.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct Capacity {
+    max_height: usize,
+    policy: OverflowPolicy,
+}
+
+impl Capacity {
+    /// Whether moving `amount` crates onto a column `height` tall breaks
+    /// the limit. Moving a column onto itself never does.
+    fn exceeded(&self, from: usize, to: usize, height: usize, amount: usize) -> bool {
+        from != to && height + amount > self.max_height
+    }
+
+    /// Where `count` crates bound for `to` can go as `(column, crates)`: as
+    /// many as fit on `to`, then on the columns after it in turn, wrapping
+    /// round and passing over `from`. `None` if the yard is too full.
+    fn spill(
+        &self,
+        heights: &[usize],
+        from: usize,
+        to: usize,
+        count: usize,
+    ) -> Option<Vec<(usize, usize)>> {
+        let mut remaining = count;
+        let mut placements = Vec::new();
+
+        for offset in 0..heights.len() {
+            if remaining == 0 {
+                break;
+            }
+
+            let col = (to - 1 + offset) % heights.len() + 1;
+
+            if col == from && col != to {
+                continue;
+            }
+
+            let placed = self
+                .max_height
+                .saturating_sub(heights[col - 1])
+                .min(remaining);
+
+            if placed > 0 {
+                placements.push((col, placed));
+                remaining -= placed;
+            }
+        }
+
+        (remaining == 0).then_some(placements)
+    }
+
+    fn overflow_error(&self, line: usize, to: usize) -> InvalidMove {
+        InvalidMove::Overflow {
+            line,
+            column: to,
+            capacity: self.max_height,
+        }
+    }
+}
+
+/// An instruction whose outcome the capacity limit changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Overflow {
+    Refused {
+        line: usize,
+    },
+    /// Some crates ended up elsewhere than the target, as `(column, crates)`.
+    Spilled {
+        line: usize,
+        spills: Vec<(usize, usize)>,
+    },
+}
+
+/// How a crane lifts the crates of a single instruction.
+trait CraneModel {
+    /// How many crates to lift next when `remaining` are still to be moved.
+    fn batch_size(&self, remaining: usize) -> usize;
+}
+
+/// Moves crates one at a time, reversing their order.
+struct CrateMover9000;
+
+impl CraneModel for CrateMover9000 {
+    fn batch_size(&self, _remaining: usize) -> usize {
+        1
+    }
+}
+
+/// Moves all crates of an instruction at once, keeping their order.
+struct CrateMover9001;
+
+impl CraneModel for CrateMover9001 {
+    fn batch_size(&self, remaining: usize) -> usize {
+        remaining
+    }
+}
+
+/// Moves at most `.0` crates at once.
+struct CrateMoverUpTo(usize);
+
+impl CraneModel for CrateMoverUpTo {
+    fn batch_size(&self, remaining: usize) -> usize {
+        self.0.min(remaining)
+    }
+}
+
+/// Picks a crane model by name: `9000`, `9001` or `upto:K`.
+fn parse_model(name: &str) -> Result<Box<dyn CraneModel>> {
+    match name {
+        "9000" => Ok(Box::new(CrateMover9000)),
+        "9001" => Ok(Box::new(CrateMover9001)),
+        _ => match name.strip_prefix("upto:").map(str::parse::<usize>) {
+            Some(Ok(limit)) if limit > 0 => Ok(Box::new(CrateMoverUpTo(limit))),
+            _ => Err(anyhow!("Unknown crane model '{}'", name)),
+        },
+    }
+}
+
+/// A single executed instruction, with the stacks on either side of it.
+#[derive(Debug)]
+struct StepEvent<'a> {
+    step: usize,
+    instruction: &'a Instruction,
+    overflow: Option<&'a Overflow>,
+    before: &'a [Stack<Label>],
+    after: &'a [Stack<Label>],
+}
+
+impl StepEvent<'_> {
+    /// How many crates the step lifted off the source column.
+    fn lifted(&self) -> usize {
+        match self.overflow {
+            Some(Overflow::Refused { .. }) => 0,
+            _ => self.instruction.amount,
+        }
+    }
+
+    /// Where the lifted crates were set down, as `(column, crates)`: all on
+    /// the target, unless some spilled past it.
+    fn landings(&self) -> Vec<(usize, usize)> {
+        let Instruction { amount, to, .. } = *self.instruction;
+
+        match self.overflow {
+            Some(Overflow::Refused { .. }) => Vec::new(),
+            Some(Overflow::Spilled { spills, .. }) => {
+                let spilled: usize = spills.iter().map(|(_, placed)| placed).sum();
+                let mut landings = vec![(to, amount - spilled)];
+                landings.extend(spills);
+                landings
+            }
+            None => vec![(to, amount)],
+        }
+    }
+}
+
+/// Receives a [`StepEvent`] for every instruction a traced simulation runs.
+trait CraneObserver {
+    fn on_step(&mut self, event: &StepEvent);
+}
+
+/// Prints the touched columns before and after every step.
+struct PrintObserver;
+
+impl CraneObserver for PrintObserver {
+    fn on_step(&mut self, event: &StepEvent) {
+        let column = |stacks: &[Stack<Label>], i: usize| {
+            stacks.get(i - 1).map_or_else(String::new, |v| {
+                v.iter().rev().map(String::as_str).collect()
+            })
+        };
+        let Instruction { from, to, .. } = *event.instruction;
+
+        println!("Step {} -----------", event.step);
+        println!("{:?}", event.instruction);
+        println!("Old {}: {}", from, column(event.before, from));
+        println!("Old {}: {}", to, column(event.before, to));
+        println!("New {}: {}", from, column(event.after, from));
+        println!("New {}: {}", to, column(event.after, to));
+    }
+}
+
+/// Tallies over a whole simulation, gathered step by step. Crates are told
+/// apart by label only, so crates sharing a label share their counts too.
+#[derive(Debug, Clone, Default)]
+struct Statistics {
+    /// Tallest each column got, and the first step it got there (0 being
+    /// the starting stacks).
+    max_heights: Vec<(usize, usize)>,
+    /// Crates taken off each column.
+    moved_out: Vec<usize>,
+    /// Crates set down on each column.
+    moved_in: Vec<usize>,
+    moves_per_crate: HashMap<Label, usize>,
+    /// Every column a crate has been on, starting with the one it began in.
+    paths: HashMap<Label, Vec<usize>>,
+}
+
+impl Statistics {
+    fn new(crane: &Crane) -> Self {
+        let mut paths: HashMap<Label, Vec<usize>> = HashMap::new();
+
+        for (i, v) in crane.boxes.iter().enumerate() {
+            for label in v {
+                paths.entry(label.clone()).or_insert_with(|| vec![i + 1]);
+            }
+        }
+
+        Self {
+            max_heights: crane.boxes.iter().map(|v| (v.len(), 0)).collect(),
+            moved_out: vec![0; crane.boxes.len()],
+            moved_in: vec![0; crane.boxes.len()],
+            moves_per_crate: HashMap::new(),
+            paths,
+        }
+    }
+
+    fn path(&self, label: &str) -> Option<&[usize]> {
+        self.paths.get(label).map(Vec::as_slice)
+    }
+}
+
+impl CraneObserver for Statistics {
+    fn on_step(&mut self, event: &StepEvent) {
+        self.moved_out[event.instruction.from - 1] += event.lifted();
+
+        // Crates that spilled past the target are followed to where they
+        // actually landed: the top of each column they were set down on
+        for (col, placed) in event.landings() {
+            let column = &event.after[col - 1];
+
+            for label in &column[column.len() - placed..] {
+                *self.moves_per_crate.entry(label.clone()).or_default() += 1;
+                self.paths.entry(label.clone()).or_default().push(col);
+            }
+
+            self.moved_in[col - 1] += placed;
+        }
+
+        for (max, v) in self.max_heights.iter_mut().zip(event.after) {
+            if v.len() > max.0 {
+                *max = (v.len(), event.step);
+            }
+        }
+    }
+}
+
+const SOURCE_COLOUR: &str = "33";
+const TARGET_COLOUR: &str = "36";
+const IN_FLIGHT_COLOUR: &str = "1;31";
+
+/// Redraws the stacks in the terminal for every step: once with the crates
+/// about to be lifted picked out, and once after they have been set down.
+struct Animator<W: Write> {
+    out: W,
+    delay: std::time::Duration,
+}
+
+impl<W: Write> Animator<W> {
+    fn frame(&mut self, title: &str, drawing: &str) -> std::io::Result<()> {
+        write!(self.out, "\x1b[2J\x1b[H{}\n\n{}", title, drawing)?;
+        self.out.flush()?;
+        std::thread::sleep(self.delay);
+
+        Ok(())
+    }
+}
+
+impl<W: Write> CraneObserver for Animator<W> {
+    fn on_step(&mut self, event: &StepEvent) {
+        let Instruction {
+            amount, from, to, ..
+        } = *event.instruction;
+        let title = format!(
+            "Step {}: move {} from {} to {}",
+            event.step, amount, from, to
+        );
+
+        // Crates in flight are the ones lifted off the source column before
+        // the move, and wherever they were set down after it. A refused move
+        // has none.
+        let paint = |stacks: &[Stack<Label>], flying: Vec<(usize, usize)>| {
+            let flight_starts: Vec<_> = flying
+                .into_iter()
+                .map(|(col, count)| (col, stacks[col - 1].len().saturating_sub(count)))
+                .collect();
+
+            move |col: usize, level: Option<usize>| {
+                let in_flight = |level| {
+                    flight_starts
+                        .iter()
+                        .any(|&(moving, start)| moving == col && level >= start)
+                };
+
+                match level {
+                    Some(level) if in_flight(level) => Some(IN_FLIGHT_COLOUR),
+                    _ if col == from => Some(SOURCE_COLOUR),
+                    _ if col == to => Some(TARGET_COLOUR),
+                    _ => None,
+                }
+            }
+        };
+
+        let before = draw(
+            event.before,
+            &paint(event.before, vec![(from, event.lifted())]),
+        );
+        let after = draw(event.after, &paint(event.after, event.landings()));
+
+        // A broken terminal shouldn't stop the simulation underneath
+        _ = self
+            .frame(&title, &before)
+            .and_then(|_| self.frame(&title, &after));
+    }
 }
 
+/// What a move search is trying to reach.
 #[derive(Debug, Clone)]
+enum Target {
+    /// The crates on top of the stacks, as [`Crane::top_crates`] reads them.
+    TopCrates(String),
+    /// Every stack, crate for crate.
+    Stacks(Vec<Stack<Label>>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct Crane {
-    boxes: HashMap<usize, Stack<char>>,
+    /// Column `i` of the diagram lives at index `i - 1`.
+    #[serde(rename = "stacks")]
+    boxes: Vec<Stack<Label>>,
+    #[serde(default)]
     instructions: VecDeque<Instruction>,
+    #[serde(default)]
+    capacity: Option<Capacity>,
+    #[serde(skip)]
     original_state: Option<Box<Crane>>,
 }
 
 impl Crane {
     fn new() -> Self {
         Self {
-            boxes: HashMap::new(),
+            boxes: Vec::new(),
             instructions: VecDeque::new(),
+            capacity: None,
             original_state: None,
         }
     }
@@ -45,125 +488,361 @@ impl Crane {
         Ok(())
     }
 
-    fn simulate_one(&mut self) -> Result<()> {
+    /// Run every pending instruction, returning the ones the capacity limit
+    /// got in the way of.
+    fn simulate(&mut self, model: &dyn CraneModel) -> Result<Vec<Overflow>> {
+        self.simulate_with(model, None)
+    }
+
+    /// Like [`Crane::simulate`], but reports every executed instruction to
+    /// `observer`. Snapshots are only taken when an observer is attached.
+    fn simulate_with(
+        &mut self,
+        model: &dyn CraneModel,
+        mut observer: Option<&mut dyn CraneObserver>,
+    ) -> Result<Vec<Overflow>> {
+        self.validate()?;
         self.save()?;
 
+        let mut step = 0;
+        let mut overflows = Vec::new();
+
         while let Some(instruction) = self.instructions.pop_front() {
-            let mut count = instruction.amount;
+            let before = observer.as_ref().map(|_| self.boxes.clone());
+
+            let overflow = self.apply(model, &instruction)?;
+            step += 1;
+
+            if let (Some(observer), Some(before)) = (observer.as_deref_mut(), before) {
+                observer.on_step(&StepEvent {
+                    step,
+                    instruction: &instruction,
+                    overflow: overflow.as_ref(),
+                    before: &before,
+                    after: &self.boxes,
+                });
+            }
 
-            while count > 0 {
-                count = self.move_one(&instruction, count);
+            overflows.extend(overflow);
+        }
+
+        Ok(overflows)
+    }
+
+    /// Dry-run the pending instructions against the stack heights alone,
+    /// stopping at the first one that could not be carried out. Stacks
+    /// that start out taller than the capacity limit allows are rejected
+    /// before any of them.
+    fn validate(&self) -> Result<(), InvalidMove> {
+        let mut heights: Vec<usize> = self.boxes.iter().map(Stack::len).collect();
+
+        if let Some(capacity) = self.capacity {
+            if let Some((i, &height)) = heights
+                .iter()
+                .enumerate()
+                .find(|(_, &height)| height > capacity.max_height)
+            {
+                return Err(InvalidMove::OverCapacity {
+                    column: i + 1,
+                    height,
+                    capacity: capacity.max_height,
+                });
+            }
+        }
+
+        for instruction in &self.instructions {
+            let line = instruction.line;
+
+            for column in [instruction.from, instruction.to] {
+                if column == 0 || column > heights.len() {
+                    return Err(InvalidMove::MissingColumn { line, column });
+                }
+            }
+
+            let available = heights[instruction.from - 1];
+
+            if instruction.amount > available {
+                return Err(InvalidMove::NotEnoughCrates {
+                    line,
+                    requested: instruction.amount,
+                    available,
+                });
+            }
+
+            let Instruction {
+                amount, from, to, ..
+            } = *instruction;
+            heights[from - 1] -= amount;
+
+            match self.capacity {
+                Some(capacity) if capacity.exceeded(from, to, heights[to - 1], amount) => {
+                    match capacity.policy {
+                        OverflowPolicy::Error => return Err(capacity.overflow_error(line, to)),
+                        OverflowPolicy::Refuse => heights[from - 1] += amount,
+                        OverflowPolicy::Spill => {
+                            let placements = capacity
+                                .spill(&heights, from, to, amount)
+                                .ok_or(capacity.overflow_error(line, to))?;
+
+                            for (col, placed) in placements {
+                                heights[col - 1] += placed;
+                            }
+                        }
+                    }
+                }
+                _ => heights[to - 1] += amount,
             }
         }
 
         Ok(())
     }
 
-    fn simulate_two(&mut self) -> Result<()> {
-        self.save()?;
+    /// Carry out a single instruction, keeping to the capacity limit if
+    /// there is one, and report how the limit changed the outcome.
+    fn apply(
+        &mut self,
+        model: &dyn CraneModel,
+        instruction: &Instruction,
+    ) -> Result<Option<Overflow>, InvalidMove> {
+        let Instruction {
+            amount,
+            from,
+            to,
+            line,
+        } = *instruction;
+
+        if let Some(capacity) = self.capacity {
+            if capacity.exceeded(from, to, self.boxes[to - 1].len(), amount) {
+                return match capacity.policy {
+                    OverflowPolicy::Error => Err(capacity.overflow_error(line, to)),
+                    OverflowPolicy::Refuse => Ok(Some(Overflow::Refused { line })),
+                    OverflowPolicy::Spill => {
+                        self.apply_spilling(model, instruction, capacity).map(Some)
+                    }
+                };
+            }
+        }
 
-        while let Some(instruction) = self.instructions.pop_front() {
-            println!("Step -----------");
-            println!("{:?}", &instruction);
-            println!(
-                "Old {}: {}",
-                &instruction.from,
-                self.boxes
-                    .get(&instruction.from)
-                    .unwrap()
-                    .iter()
-                    .collect::<String>()
-            );
-            println!(
-                "Old {}: {}",
-                &instruction.to,
-                self.boxes
-                    .get(&instruction.to)
-                    .unwrap()
-                    .iter()
-                    .collect::<String>()
-            );
-            let mut count = instruction.amount.clone();
+        let mut count = amount;
+
+        while count > 0 {
+            let batch = model.batch_size(count).clamp(1, count);
+            count = self.move_batch(instruction, count, batch);
+        }
+
+        Ok(None)
+    }
+
+    /// Like [`Crane::apply`], but whatever part of a batch doesn't fit on
+    /// the target is shared out over the columns after it.
+    fn apply_spilling(
+        &mut self,
+        model: &dyn CraneModel,
+        instruction: &Instruction,
+        capacity: Capacity,
+    ) -> Result<Overflow, InvalidMove> {
+        let Instruction {
+            amount,
+            from,
+            to,
+            line,
+        } = *instruction;
+        let mut spills: Vec<(usize, usize)> = Vec::new();
+        let mut count = amount;
+
+        while count > 0 {
+            let batch = model.batch_size(count).clamp(1, count);
+            let current_col = &mut self.boxes[from - 1];
+            let mut lifted = current_col.split_off(current_col.len() - batch);
+
+            let heights: Vec<usize> = self.boxes.iter().map(Stack::len).collect();
+            let placements = capacity
+                .spill(&heights, from, to, batch)
+                .ok_or(capacity.overflow_error(line, to))?;
+
+            // The bottom of the batch is set down first
+            for (col, placed) in placements {
+                self.boxes[col - 1].extend(lifted.drain(..placed));
+
+                if col == to {
+                    continue;
+                }
+
+                match spills.iter_mut().find(|(c, _)| *c == col) {
+                    Some((_, total)) => *total += placed,
+                    None => spills.push((col, placed)),
+                }
+            }
+
+            count -= batch;
+        }
+
+        Ok(Overflow::Spilled { line, spills })
+    }
+
+    /// Lift the top `batch` crates of the source column in one go and set
+    /// them down on the target column, keeping their order.
+    fn move_batch(&mut self, instruction: &Instruction, count: usize, batch: usize) -> usize {
+        let current_col = &mut self.boxes[instruction.from - 1];
+        let lifted = current_col.split_off(current_col.len() - batch);
+
+        self.boxes[instruction.to - 1].extend(lifted);
+
+        count - batch
+    }
+
+    /// Take the stacks as the state the pending instructions left behind and
+    /// undo them, last first, so that `boxes` ends up as the stacks they were
+    /// run on. The instructions stay in place to be replayed. Any capacity
+    /// limit is ignored, as a spilled or refused move can't be told apart
+    /// from the stacks alone.
+    fn reverse(&mut self, model: &dyn CraneModel) -> Result<(), InvalidMove> {
+        for instruction in self.instructions.iter().rev() {
+            let line = instruction.line;
+
+            for column in [instruction.from, instruction.to] {
+                if column == 0 || column > self.boxes.len() {
+                    return Err(InvalidMove::MissingColumn { line, column });
+                }
+            }
+
+            let available = self.boxes[instruction.to - 1].len();
+
+            if instruction.amount > available {
+                return Err(InvalidMove::NotEnoughCrates {
+                    line,
+                    requested: instruction.amount,
+                    available,
+                });
+            }
+
+            let mut batches = Vec::new();
+            let mut count = instruction.amount;
 
             while count > 0 {
-                count = self.move_three(&instruction, count);
+                let batch = model.batch_size(count).clamp(1, count);
+                batches.push(batch);
+                count -= batch;
+            }
+
+            // The last batch set down is the first one to pick back up
+            for batch in batches.into_iter().rev() {
+                let target_col = &mut self.boxes[instruction.to - 1];
+                let lifted = target_col.split_off(target_col.len() - batch);
+
+                self.boxes[instruction.from - 1].extend(lifted);
             }
-            println!(
-                "New {}: {}",
-                &instruction.from,
-                self.boxes
-                    .get(&instruction.from)
-                    .unwrap()
-                    .iter()
-                    .collect::<String>()
-            );
-            println!(
-                "New {}: {}",
-                &instruction.to,
-                self.boxes
-                    .get(&instruction.to)
-                    .unwrap()
-                    .iter()
-                    .collect::<String>()
-            );
         }
 
         Ok(())
     }
 
-    fn move_one(&mut self, instruction: &Instruction, count: usize) -> usize {
-        let current_box = self
-            .boxes
-            .get_mut(&instruction.from)
-            .expect("Missing column?")
-            .pop_front()
-            .expect("Popped from an empty stack :(");
+    /// Breadth-first search for the fewest instructions that take the
+    /// current stacks to `target` under `model`, trying no more than
+    /// `max_depth` moves. Pending instructions are ignored.
+    fn plan(
+        &self,
+        target: &Target,
+        model: &dyn CraneModel,
+        max_depth: usize,
+    ) -> Option<Vec<Instruction>> {
+        let reached = |crane: &Crane| match target {
+            Target::TopCrates(tops) => crane.top_crates() == *tops,
+            Target::Stacks(stacks) => crane.boxes == *stacks,
+        };
+
+        let mut start = Crane::new();
+        start.boxes = self.boxes.clone();
+        start.capacity = self.capacity;
+
+        // Every state seen, with the state it came from and the move between
+        let mut seen: Vec<(Crane, Option<(usize, Instruction)>)> = vec![(start, None)];
+        let mut visited: HashSet<Vec<Stack<Label>>> = HashSet::from([self.boxes.clone()]);
+        let mut frontier = 0..1;
+
+        for depth in 0..=max_depth {
+            if let Some(found) = frontier.clone().find(|&i| reached(&seen[i].0)) {
+                let mut moves = Vec::new();
+                let mut current = found;
+
+                while let Some((parent, instruction)) = &seen[current].1 {
+                    moves.push(instruction.clone());
+                    current = *parent;
+                }
 
-        self.boxes
-            .entry(instruction.to.clone())
-            .and_modify(|v| v.push_front(current_box));
+                moves.reverse();
+                return Some(moves);
+            }
 
-        count - 1
-    }
+            if depth == max_depth {
+                break;
+            }
 
-    fn move_three(&mut self, instruction: &Instruction, count: usize) -> usize {
-        let subcount = count;
+            let next_start = seen.len();
 
-        let current_col = self
-            .boxes
-            .get_mut(&instruction.from)
-            .expect("Missing column?");
+            for parent in frontier {
+                let columns = seen[parent].0.boxes.len();
 
-        let mut tmp: VecDeque<_> = current_col.drain(..subcount).collect();
-        println!("Drained: {:?}", tmp.iter().collect::<String>());
+                for from in 1..=columns {
+                    for to in (1..=columns).filter(|&to| to != from) {
+                        for amount in 1..=seen[parent].0.boxes[from - 1].len() {
+                            let instruction = Instruction {
+                                amount,
+                                from,
+                                to,
+                                line: 0,
+                            };
 
-        let target_col = self
-            .boxes
-            .get_mut(&instruction.to)
-            .expect("Missing target column?");
+                            let mut next = seen[parent].0.clone();
+
+                            if next.apply(model, &instruction).is_err() {
+                                continue;
+                            }
+
+                            if visited.insert(next.boxes.clone()) {
+                                seen.push((next, Some((parent, instruction))));
+                            }
+                        }
+                    }
+                }
+            }
+
+            frontier = next_start..seen.len();
+        }
 
-        tmp.append(target_col);
+        None
+    }
 
-        _ = self.boxes.entry(instruction.to).and_modify(|v| *v = tmp);
+    /// How many columns the diagram footer numbered, counting any that never
+    /// had a crate.
+    fn columns(&self) -> usize {
+        self.boxes.len()
+    }
 
-        count - subcount
+    /// The stack in column `col`, empty for a column the crane doesn't have.
+    fn stack(&self, col: usize) -> &[Label] {
+        col.checked_sub(1)
+            .and_then(|i| self.boxes.get(i))
+            .map_or(&[], Vec::as_slice)
     }
 
     fn top_crates(&self) -> String {
-        let mut chars = Vec::new();
-        chars.resize(self.boxes.len(), ' ');
+        self.top_crates_with(' ')
+    }
 
-        let tops: Vec<_> = self
-            .boxes
-            .iter()
-            .map(|(k, v)| (k.clone(), v.front().map_or(' ', |c| c.clone())))
-            .collect();
+    /// The crate on top of every column in turn, with `placeholder` standing
+    /// in for the empty ones.
+    fn top_crates_with(&self, placeholder: char) -> String {
+        let mut tops = String::new();
 
-        for (k, v) in tops.into_iter() {
-            chars[k - 1] = v;
+        for col in 1..=self.columns() {
+            match self.stack(col).last() {
+                Some(label) => tops.push_str(label),
+                None => tops.push(placeholder),
+            }
         }
 
-        chars.into_iter().collect()
+        tops
     }
 
     fn reset(&mut self) {
@@ -176,66 +855,204 @@ impl Crane {
         }
     }
 
-    fn parse_line(&mut self, line: &str) {
-        if line.starts_with(" 1") || line.is_empty() {
-            return;
+    /// Write the stacks and pending instructions to `path`, as JSON or TOML
+    /// depending on its extension.
+    fn save_to(&self, path: &Path) -> Result<()> {
+        let contents = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => serde_json::to_string_pretty(self)?,
+            Some("toml") => toml::to_string(self)?,
+            _ => bail!(
+                "Don't know how to save {}, use .json or .toml",
+                path.display()
+            ),
+        };
+
+        Ok(std::fs::write(path, contents)?)
+    }
+
+    /// Read back a crane written by [`Crane::save_to`].
+    fn load_from(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => Ok(serde_json::from_str(&contents)?),
+            Some("toml") => Ok(toml::from_str(&contents)?),
+            _ => bail!(
+                "Don't know how to load {}, use .json or .toml",
+                path.display()
+            ),
         }
+    }
+
+    /// Parse a line below the diagram. Anything after a `#` is a comment.
+    fn parse_line(&mut self, number: usize, line: &str) -> Result<()> {
+        let line = line.split('#').next().unwrap_or_default().trim();
 
-        if line.starts_with("move") {
-            self.parse_move(line);
-        } else {
-            self.parse_box_row(line);
+        if line.is_empty() {
+            return Ok(());
         }
+
+        self.parse_move(number, line)
     }
 
-    fn parse_box_row(&mut self, line: &str) {
-        let mut space_counter = 0;
-        let mut col_idx = 0;
+    /// Stack up the crates of the diagram rows (given top first), putting
+    /// each crate in the footer column it sits closest to.
+    fn parse_diagram(&mut self, rows: &[(usize, &str)], footer: &[(usize, usize)]) -> Result<()> {
+        let columns = footer.iter().map(|(col, _)| *col).max().unwrap_or(0);
+        let mut numbered = vec![false; columns];
+
+        for (col, _) in footer {
+            if std::mem::replace(&mut numbered[col - 1], true) {
+                bail!("column {} is numbered twice in the footer", col);
+            }
+        }
+
+        // Columns the footer skips still get an (empty) stack
+        self.boxes = vec![Stack::new(); columns];
+
+        for (number, row) in rows.iter().rev() {
+            let mut filled = vec![false; columns];
 
-        let mut chars_iter = line.chars();
+            for (center, label) in
+                parse_box_row(row).map_err(|e| anyhow!("line {}: {}", number, e))?
+            {
+                if footer.is_empty() {
+                    bail!("line {}: no columns to put crates in", number);
+                }
 
-        while let Some(c) = chars_iter.next() {
-            if c == ' ' {
-                space_counter += 1;
+                let col = locate(footer, center, &label).ok_or_else(|| {
+                    anyhow!(
+                        "line {}: crate '{}' doesn't line up with a column number",
+                        number,
+                        label
+                    )
+                })?;
 
-                if space_counter == 4 {
-                    col_idx += 1;
-                    space_counter = 0;
+                if std::mem::replace(&mut filled[col - 1], true) {
+                    bail!("line {}: two crates in column {}", number, col);
                 }
-            } else if c == '[' {
-                // Reset space counter in case there was a situation like
-                // [a]_[b]_[c]
-                space_counter = 0;
 
-                // There is always a char after [
-                // and a ] after the char
-                let letter = chars_iter.next().unwrap();
-                _ = self
-                    .boxes
-                    .entry(col_idx + 1)
-                    .and_modify(|v| v.push_back(letter))
-                    .or_insert(VecDeque::from([letter]));
+                self.boxes[col - 1].push(label);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Parse either `move 3 from 1 to 2` or the shorthand `3 1->2`.
+    fn parse_move(&mut self, number: usize, line: &str) -> Result<()> {
+        let cap = MOVE_PATTERNS
+            .iter()
+            .find_map(|pat| pat.captures(line))
+            .ok_or_else(|| anyhow!("line {}: unrecognised instruction '{}'", number, line))?;
+
+        let field = |i: usize| {
+            cap[i]
+                .parse::<usize>()
+                .map_err(|e| anyhow!("line {}: {}", number, e))
+        };
+
+        self.instructions.push_back(Instruction {
+            amount: field(1)?,
+            from: field(2)?,
+            to: field(3)?,
+            line: number,
+        });
+
+        Ok(())
+    }
+}
+
+/// The footer column a crate centred at `center` sits over. The crate has to
+/// be nearer that column's number than any other, and no further from it
+/// than half the way to the next number along; a lone column allows for
+/// where a neighbour would be.
+fn locate(footer: &[(usize, usize)], center: usize, label: &str) -> Option<usize> {
+    let distance = |i: usize| footer[i].1.abs_diff(center);
+    let nearest = (0..footer.len()).min_by_key(|&i| distance(i))?;
+
+    // Midway between two numbers belongs to neither
+    if (0..footer.len()).any(|i| i != nearest && distance(i) == distance(nearest)) {
+        return None;
+    }
+
+    let (_, col_center) = footer[nearest];
+    let toward = if center >= col_center {
+        footer.get(nearest + 1)
+    } else {
+        nearest.checked_sub(1).map(|i| &footer[i])
+    };
+    let neighbour = toward
+        .or_else(|| footer.get(nearest + 1))
+        .or_else(|| nearest.checked_sub(1).map(|i| &footer[i]));
+
+    // Centers are doubled, like the cell width plus a separating space
+    let spacing = neighbour.map_or(2 * (label.chars().count() + 3), |(_, other)| {
+        other.abs_diff(col_center)
+    });
+
+    (2 * distance(nearest) <= spacing).then_some(footer[nearest].0)
+}
 
-                col_idx += 1;
+/// Reads the `[A] [B]` cells of a diagram row as `(center, label)` pairs,
+/// where `center` is twice the mean character position of the cell.
+fn parse_box_row(line: &str) -> Result<Vec<(usize, Label)>> {
+    let mut crates = Vec::new();
+    let mut chars = line.chars().enumerate();
+
+    while let Some((start, c)) = chars.next() {
+        match c {
+            '[' => {
+                let mut label = Label::new();
+
+                let end = loop {
+                    match chars.next() {
+                        Some((end, ']')) => break end,
+                        Some((_, c)) => label.push(c),
+                        None => bail!("unclosed '[' at position {}", start + 1),
+                    }
+                };
+
+                if label.is_empty() {
+                    bail!("empty crate at position {}", start + 1);
+                }
 
-                // Advance to the ]
-                _ = chars_iter.next().unwrap();
+                crates.push((start + end, label));
             }
+            c if c.is_whitespace() => {}
+            c => bail!("unexpected '{}' at position {}", c, start + 1),
         }
     }
 
-    fn parse_move(&mut self, line: &str) {
-        let pat = Regex::new(r"move (\d+) from (\d+) to (\d+)").unwrap();
+    Ok(crates)
+}
+
+/// Reads the line of column numbers under the diagram as `(column, center)`
+/// pairs, or `None` if the line is anything else.
+fn parse_footer(line: &str) -> Option<Vec<(usize, usize)>> {
+    let mut columns = Vec::new();
+    let mut chars = line.chars().enumerate().peekable();
+
+    while let Some((start, c)) = chars.next() {
+        if c.is_whitespace() {
+            continue;
+        }
+
+        let mut digits = String::from(c);
+        let mut end = start;
 
-        for cap in pat.captures_iter(line) {
-            let amount = cap[1].parse().unwrap();
-            let from = cap[2].parse().unwrap();
-            let to = cap[3].parse().unwrap();
+        while let Some((i, c)) = chars.next_if(|(_, c)| !c.is_whitespace()) {
+            digits.push(c);
+            end = i;
+        }
 
-            self.instructions
-                .push_back(Instruction { amount, from, to });
+        match digits.parse::<usize>() {
+            Ok(col) if col > 0 => columns.push((col, start + end)),
+            _ => return None,
         }
     }
+
+    (!columns.is_empty()).then_some(columns)
 }
 
 impl FromStr for Crane {
@@ -243,40 +1060,403 @@ impl FromStr for Crane {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         let mut crane = Crane::new();
+        let mut lines = s.lines().enumerate().map(|(i, line)| (i + 1, line));
+        let mut rows = Vec::new();
+        let mut footer = None;
+
+        for (number, line) in lines.by_ref() {
+            footer = parse_footer(line);
+
+            if footer.is_some() {
+                break;
+            }
+
+            if !line.trim().is_empty() {
+                rows.push((number, line));
+            }
+        }
+
+        match footer {
+            Some(footer) => crane.parse_diagram(&rows, &footer)?,
+            None => {
+                if let Some((number, _)) = rows.first() {
+                    bail!("line {}: diagram has no line of column numbers", number);
+                }
+            }
+        }
 
-        for line in s.lines() {
-            crane.parse_line(line);
+        for (number, line) in lines {
+            crane.parse_line(number, line)?;
         }
 
         Ok(crane)
     }
 }
 
-fn main() -> Result<()> {
-    let mut crane: Crane = std::fs::read_to_string("inputs/day5.prod")?.parse()?;
+/// Steps a crane through its instructions one at a time, remembering what
+/// every step lifted so that it can be taken back again.
+struct Debugger<'a> {
+    crane: Crane,
+    model: &'a dyn CraneModel,
+    history: Vec<(Instruction, Stack<Label>, Option<Overflow>)>,
+}
 
-    println!("{:?}", crane.boxes);
+impl<'a> Debugger<'a> {
+    fn new(crane: Crane, model: &'a dyn CraneModel) -> Result<Self> {
+        crane.validate()?;
 
-    crane.simulate_one()?;
+        Ok(Self {
+            crane,
+            model,
+            history: Vec::new(),
+        })
+    }
 
-    println!("Top crates: '{}'", crane.top_crates());
+    /// Number of instructions applied so far.
+    fn position(&self) -> usize {
+        self.history.len()
+    }
 
-    crane.reset();
-    crane.simulate_two()?;
-    println!("Top crates: '{}'", crane.top_crates());
+    fn total(&self) -> usize {
+        self.history.len() + self.crane.instructions.len()
+    }
 
-    Ok(())
-}
+    fn step(&mut self) -> bool {
+        let Some(instruction) = self.crane.instructions.pop_front() else {
+            return false;
+        };
 
-#[cfg(test)]
-mod test {
-    use anyhow::Result;
+        let source = &self.crane.boxes[instruction.from - 1];
+        let lifted = source[source.len() - instruction.amount..].to_vec();
 
-    fn view_stack(crane: &super::Crane, i: &usize) -> String {
-        format!("{:?}", crane.boxes.get(i).unwrap())
+        let overflow = self
+            .crane
+            .apply(self.model, &instruction)
+            .expect("Instructions are validated up front");
+        self.history.push((instruction, lifted, overflow));
+
+        true
     }
 
-    #[test]
+    /// Undo the last step: whatever it set down on the target column (or
+    /// spilled past it) goes back onto the source column exactly as it was
+    /// lifted.
+    fn back(&mut self) -> bool {
+        let Some((instruction, lifted, overflow)) = self.history.pop() else {
+            return false;
+        };
+
+        let spills = match &overflow {
+            Some(Overflow::Refused { .. }) => None,
+            Some(Overflow::Spilled { spills, .. }) => Some(spills.as_slice()),
+            None => Some(&[][..]),
+        };
+
+        if let Some(spills) = spills {
+            let mut on_target = instruction.amount;
+
+            for &(col, placed) in spills {
+                let column = &mut self.crane.boxes[col - 1];
+                column.truncate(column.len() - placed);
+                on_target -= placed;
+            }
+
+            let target = &mut self.crane.boxes[instruction.to - 1];
+            target.truncate(target.len() - on_target);
+
+            self.crane.boxes[instruction.from - 1].extend(lifted);
+        }
+
+        self.crane.instructions.push_front(instruction);
+
+        true
+    }
+
+    fn run(&mut self, n: usize) -> usize {
+        (0..n).take_while(|_| self.step()).count()
+    }
+
+    fn goto(&mut self, k: usize) {
+        while self.position() > k && self.back() {}
+        while self.position() < k && self.step() {}
+    }
+
+    fn show(&self) -> String {
+        self.crane.to_string()
+    }
+
+    /// Read commands from `input` until it runs dry or says `quit`.
+    fn repl(&mut self, input: impl BufRead, mut output: impl Write) -> Result<()> {
+        writeln!(output, "at step {}/{}", self.position(), self.total())?;
+
+        for line in input.lines() {
+            let line = line?;
+            let mut words = line.split_whitespace();
+            let arg = |word: Option<&str>| word.map_or(Ok(1), str::parse::<usize>);
+
+            match (words.next(), words.next()) {
+                (Some("step" | "s"), None) => {
+                    if !self.step() {
+                        writeln!(output, "no instructions left")?;
+                    }
+                }
+                (Some("back" | "b"), None) => {
+                    if !self.back() {
+                        writeln!(output, "already at the start")?;
+                    }
+                }
+                (Some("run" | "r"), n) => match arg(n) {
+                    Ok(n) => _ = self.run(n),
+                    Err(e) => writeln!(output, "bad count: {}", e)?,
+                },
+                (Some("goto" | "g"), Some(k)) => match k.parse() {
+                    Ok(k) => self.goto(k),
+                    Err(e) => writeln!(output, "bad step: {}", e)?,
+                },
+                (Some("show"), None) => write!(output, "{}", self.show())?,
+                (Some("save"), Some(path)) => match self.crane.save_to(Path::new(path)) {
+                    Ok(()) => writeln!(output, "saved to {}", path)?,
+                    Err(e) => writeln!(output, "could not save: {}", e)?,
+                },
+                (Some("quit" | "q"), None) => break,
+                (None, _) => {}
+                _ => writeln!(
+                    output,
+                    "commands: step, back, run N, goto K, show, save PATH, quit"
+                )?,
+            }
+
+            writeln!(output, "at step {}/{}", self.position(), self.total())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Draw `stacks` the way the puzzle input does, with a footer of column
+/// numbers. Columns are widened to fit the longest label or column number.
+///
+/// `paint(column, level)` can pick an ANSI colour code for the crate at
+/// `level` of `column`, or for the column's number when `level` is `None`.
+fn draw(
+    stacks: &[Stack<Label>],
+    paint: &dyn Fn(usize, Option<usize>) -> Option<&'static str>,
+) -> String {
+    let height = stacks.iter().map(Stack::len).max().unwrap_or(0);
+    let width = stacks
+        .iter()
+        .flatten()
+        .map(|label| label.chars().count() + 2)
+        .chain([3, stacks.len().to_string().len()])
+        .max()
+        .unwrap_or(3);
+
+    let painted = |cell: String, col: usize, level: Option<usize>| match paint(col, level) {
+        Some(colour) => format!("\x1b[{}m{}\x1b[0m", colour, cell),
+        None => cell,
+    };
+
+    let mut drawing = String::new();
+
+    for level in (0..height).rev() {
+        let cells: Vec<String> = stacks
+            .iter()
+            .enumerate()
+            .map(|(i, v)| match v.get(level) {
+                Some(label) => painted(
+                    format!("{:^width$}", format!("[{}]", label)),
+                    i + 1,
+                    Some(level),
+                ),
+                None => " ".repeat(width),
+            })
+            .collect();
+
+        drawing.push_str(&cells.join(" "));
+        drawing.push('\n');
+    }
+
+    let footer: Vec<String> = (1..=stacks.len())
+        .map(|col| painted(format!("{:^width$}", col), col, None))
+        .collect();
+
+    drawing.push_str(&footer.join(" "));
+    drawing.push('\n');
+
+    drawing
+}
+
+/// Draws the stacks the way the puzzle input does, so that the output
+/// parses back into the same crane.
+impl fmt::Display for Crane {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", draw(&self.boxes, &|_, _| None))
+    }
+}
+
+/// The value given after `flag` on the command line, if any.
+fn flag_value(flag: &str) -> Option<String> {
+    let mut args = std::env::args().skip_while(|arg| arg != flag);
+    args.next()?;
+    args.next()
+}
+
+fn main() -> Result<()> {
+    let mut crane: Crane = match flag_value("--load") {
+        Some(path) => Crane::load_from(Path::new(&path))?,
+        None => std::fs::read_to_string("inputs/day5.prod")?.parse()?,
+    };
+
+    let placeholder = flag_value("--placeholder").map_or(Ok(' '), |p| p.parse())?;
+
+    if let Some(max_height) = flag_value("--capacity") {
+        let policy = flag_value("--overflow").map_or(Ok(OverflowPolicy::Error), |p| p.parse())?;
+
+        crane.capacity = Some(Capacity {
+            max_height: max_height.parse()?,
+            policy,
+        });
+    }
+
+    if let Some(path) = flag_value("--save") {
+        return crane.save_to(Path::new(&path));
+    }
+
+    if std::env::args().any(|arg| arg == "--check") {
+        match crane.validate() {
+            Ok(()) => println!("All {} moves are valid", crane.instructions.len()),
+            Err(e) => println!("Invalid move: {}", e),
+        }
+
+        return Ok(());
+    }
+
+    if std::env::args().any(|arg| arg == "--reverse") {
+        let mut before_9000 = crane.clone();
+        before_9000.reverse(&CrateMover9000)?;
+        println!("Starting stacks with the CrateMover 9000:\n{}", before_9000);
+
+        crane.reverse(&CrateMover9001)?;
+        println!("Starting stacks with the CrateMover 9001:\n{}", crane);
+
+        return Ok(());
+    }
+
+    let target = match (flag_value("--plan"), flag_value("--plan-to")) {
+        (Some(tops), _) => Some(Target::TopCrates(tops)),
+        (None, Some(path)) => {
+            let mut stacks = Crane::load_from(Path::new(&path))?.boxes;
+
+            // Trailing empty columns may be left out of the target
+            if stacks.len() < crane.columns() {
+                stacks.resize(crane.columns(), Stack::new());
+            }
+
+            Some(Target::Stacks(stacks))
+        }
+        (None, None) => None,
+    };
+
+    if let Some(target) = target {
+        let depth = flag_value("--depth").map_or(Ok(2), |d| d.parse())?;
+        let model = parse_model(&flag_value("--model").unwrap_or_else(|| "9001".to_owned()))?;
+
+        match crane.plan(&target, model.as_ref(), depth) {
+            Some(moves) => {
+                for Instruction {
+                    amount, from, to, ..
+                } in moves
+                {
+                    println!("move {} from {} to {}", amount, from, to);
+                }
+            }
+            None => println!("No plan within {} moves", depth),
+        }
+
+        return Ok(());
+    }
+
+    if std::env::args().any(|arg| arg == "--debug") {
+        let stdin = std::io::stdin();
+        return Debugger::new(crane, &CrateMover9001)?.repl(stdin.lock(), std::io::stdout());
+    }
+
+    print!("{}", crane);
+
+    crane.simulate(&CrateMover9000)?;
+
+    println!("Top crates: '{}'", crane.top_crates_with(placeholder));
+
+    crane.reset();
+
+    if std::env::args().any(|arg| arg == "--animate") {
+        let delay = flag_value("--delay").map_or(Ok(200), |d| d.parse())?;
+        let mut animator = Animator {
+            out: std::io::stdout(),
+            delay: std::time::Duration::from_millis(delay),
+        };
+
+        crane.simulate_with(&CrateMover9001, Some(&mut animator))?;
+    } else if std::env::args().any(|arg| arg == "--stats") {
+        let mut stats = Statistics::new(&crane);
+        crane.simulate_with(&CrateMover9001, Some(&mut stats))?;
+
+        for (i, (height, step)) in stats.max_heights.iter().enumerate() {
+            println!(
+                "Column {}: {} out, {} in, tallest at {} crates on step {}",
+                i + 1,
+                stats.moved_out[i],
+                stats.moved_in[i],
+                height,
+                step
+            );
+        }
+
+        if let Some((label, count)) = stats.moves_per_crate.iter().max_by_key(|(_, c)| **c) {
+            println!("Most moved crate: {} ({} moves)", label, count);
+        }
+
+        if let Some(label) = flag_value("--path") {
+            println!("Path of {}: {:?}", label, stats.path(&label));
+        }
+    } else if std::env::args().any(|arg| arg == "--trace") {
+        crane.simulate_with(&CrateMover9001, Some(&mut PrintObserver))?;
+    } else {
+        let overflows = crane.simulate(&CrateMover9001)?;
+
+        if !overflows.is_empty() {
+            println!("Moves affected by the capacity limit: {:?}", overflows);
+        }
+    }
+
+    println!("Top crates: '{}'", crane.top_crates_with(placeholder));
+
+    crane.reset();
+    crane.simulate(&CrateMoverUpTo(3))?;
+    println!(
+        "Top crates (up to 3 at once): '{}'",
+        crane.top_crates_with(placeholder)
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use anyhow::Result;
+
+    /// The column from the top down, as it reads in the diagram.
+    fn view_stack(crane: &super::Crane, i: &usize) -> String {
+        let labels: Vec<_> = crane.boxes[i - 1]
+            .iter()
+            .rev()
+            .map(|label| format!("'{}'", label))
+            .collect();
+
+        format!("[{}]", labels.join(", "))
+    }
+
+    #[test]
     fn test_one() -> Result<()> {
         let mut crane: super::Crane = std::fs::read_to_string("inputs/day5.test")?.parse()?;
 
@@ -284,7 +1464,7 @@ mod test {
         assert_eq!(view_stack(&crane, &2), "['D', 'C', 'M']");
         assert_eq!(view_stack(&crane, &3), "['P']");
 
-        crane.simulate_one()?;
+        crane.simulate(&super::CrateMover9000)?;
 
         assert_eq!(view_stack(&crane, &1), "['C']");
         assert_eq!(view_stack(&crane, &2), "['M']");
@@ -322,7 +1502,7 @@ mod test {
             "['L', 'M', 'F', 'J', 'N', 'Q', 'W']"
         );
 
-        crane.simulate_one()?;
+        crane.simulate(&super::CrateMover9000)?;
 
         assert_eq!(crane.top_crates(), "VQZNJMWTR");
 
@@ -333,11 +1513,7 @@ mod test {
     fn test_two() -> Result<()> {
         let mut crane: super::Crane = std::fs::read_to_string("inputs/day5.test")?.parse()?;
 
-        fn view_stack(crane: &super::Crane, i: &usize) -> String {
-            format!("{:?}", crane.boxes.get(i).unwrap())
-        }
-
-        crane.simulate_two()?;
+        crane.simulate(&super::CrateMover9001)?;
 
         assert_eq!(view_stack(&crane, &1), "['M']");
         assert_eq!(view_stack(&crane, &2), "['C']");
@@ -345,4 +1521,740 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    fn test_up_to() -> Result<()> {
+        let mut crane: super::Crane = std::fs::read_to_string("inputs/day5.test")?.parse()?;
+
+        crane.simulate(&super::CrateMoverUpTo(1))?;
+        assert_eq!(crane.top_crates(), "CMZ");
+
+        crane.reset();
+        crane.simulate(&super::CrateMoverUpTo(2))?;
+
+        assert_eq!(view_stack(&crane, &1), "['M']");
+        assert_eq!(view_stack(&crane, &2), "['C']");
+        assert_eq!(view_stack(&crane, &3), "['Z', 'D', 'N', 'P']");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_observer() -> Result<()> {
+        use super::{CraneObserver, StepEvent};
+
+        #[derive(Default)]
+        struct Recorder(Vec<(usize, usize, String, String)>);
+
+        impl CraneObserver for Recorder {
+            fn on_step(&mut self, event: &StepEvent) {
+                let to = event.instruction.to;
+                let column = |stacks: &[super::Stack<super::Label>]| {
+                    stacks[to - 1]
+                        .iter()
+                        .rev()
+                        .map(String::as_str)
+                        .collect::<String>()
+                };
+
+                self.0.push((
+                    event.step,
+                    event.instruction.amount,
+                    column(event.before),
+                    column(event.after),
+                ));
+            }
+        }
+
+        let mut crane: super::Crane = std::fs::read_to_string("inputs/day5.test")?.parse()?;
+        let mut recorder = Recorder::default();
+
+        crane.simulate_with(&super::CrateMover9001, Some(&mut recorder))?;
+
+        assert_eq!(
+            recorder.0,
+            vec![
+                (1, 1, "NZ".to_owned(), "DNZ".to_owned()),
+                (2, 3, "P".to_owned(), "DNZP".to_owned()),
+                (3, 2, "".to_owned(), "CM".to_owned()),
+                (4, 1, "".to_owned(), "C".to_owned()),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_debugger() -> Result<()> {
+        let crane: super::Crane = std::fs::read_to_string("inputs/day5.test")?.parse()?;
+        let mut debugger = super::Debugger::new(crane.clone(), &super::CrateMover9000)?;
+
+        assert!(debugger.step());
+        assert_eq!(view_stack(&debugger.crane, &1), "['D', 'N', 'Z']");
+
+        assert!(debugger.back());
+        assert!(!debugger.back());
+        assert_eq!(debugger.crane.boxes, crane.boxes);
+
+        assert_eq!(debugger.run(10), 4);
+        assert_eq!(debugger.crane.top_crates(), "CMZ");
+
+        debugger.goto(2);
+        assert_eq!(
+            debugger.show(),
+            "        [Z]\n        [N]\n    [C] [D]\n    [M] [P]\n 1   2   3 \n"
+        );
+
+        debugger.goto(0);
+        assert_eq!(debugger.crane.boxes, crane.boxes);
+        assert_eq!(debugger.crane.instructions.len(), 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_repl() -> Result<()> {
+        let crane: super::Crane = std::fs::read_to_string("inputs/day5.test")?.parse()?;
+        let mut debugger = super::Debugger::new(crane, &super::CrateMover9001)?;
+        let mut output = Vec::new();
+
+        debugger.repl(
+            "step\nrun 2\nback\nshow\nfly\nquit\nstep\n".as_bytes(),
+            &mut output,
+        )?;
+
+        assert_eq!(
+            String::from_utf8(output)?,
+            concat!(
+                "at step 0/4\n",
+                "at step 1/4\n",
+                "at step 3/4\n",
+                "at step 2/4\n",
+                "        [D]\n",
+                "        [N]\n",
+                "    [C] [Z]\n",
+                "    [M] [P]\n",
+                " 1   2   3 \n",
+                "at step 2/4\n",
+                "commands: step, back, run N, goto K, show, save PATH, quit\n",
+                "at step 2/4\n",
+            )
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_render() -> Result<()> {
+        let input = std::fs::read_to_string("inputs/day5.test")?;
+        let crane: super::Crane = input.parse()?;
+
+        assert!(input.starts_with(&crane.to_string()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_render_round_trip() -> Result<()> {
+        for path in ["inputs/day5.test", "inputs/day5.prod"] {
+            let mut crane: super::Crane = std::fs::read_to_string(path)?.parse()?;
+            let parsed: super::Crane = crane.to_string().parse()?;
+            assert_eq!(parsed.boxes, crane.boxes);
+
+            crane.simulate(&super::CrateMover9001)?;
+            let parsed: super::Crane = crane.to_string().parse()?;
+            assert_eq!(parsed.boxes, crane.boxes);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate() -> Result<()> {
+        use super::InvalidMove;
+
+        let input = std::fs::read_to_string("inputs/day5.test")?;
+        let crane: super::Crane = input.parse()?;
+        assert_eq!(crane.validate(), Ok(()));
+
+        let mut crane: super::Crane = format!("{}move 2 from 1 to 3\n", input).parse()?;
+        let before = crane.clone();
+
+        assert_eq!(
+            crane.validate(),
+            Err(InvalidMove::NotEnoughCrates {
+                line: 10,
+                requested: 2,
+                available: 1
+            })
+        );
+        assert_eq!(crane.boxes, before.boxes);
+        assert!(crane.simulate(&super::CrateMover9001).is_err());
+        assert_eq!(crane.boxes, before.boxes);
+        assert_eq!(crane.instructions.len(), 5);
+
+        let crane: super::Crane = format!("{}move 1 from 4 to 1\n", input).parse()?;
+        assert_eq!(
+            crane.validate(),
+            Err(InvalidMove::MissingColumn {
+                line: 10,
+                column: 4
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_wide_diagram() -> Result<()> {
+        let input = concat!(
+            "                                             [J]\n",
+            "[AB]                                         [K]  [L] \n",
+            "[C]  [D]  [E]  [F]  [G]  [H]  [I]  [XY] \n",
+            " 1    2    3    4    5    6    7    8    9    10   11\n",
+            "\n",
+            "move 2 from 10 to 1\n",
+        );
+        let mut crane: super::Crane = input.parse()?;
+
+        assert_eq!(crane.boxes.len(), 11);
+        assert_eq!(view_stack(&crane, &1), "['AB', 'C']");
+        assert_eq!(view_stack(&crane, &8), "['XY']");
+        assert_eq!(view_stack(&crane, &9), "[]");
+        assert_eq!(view_stack(&crane, &10), "['J', 'K']");
+        assert_eq!(view_stack(&crane, &11), "['L']");
+
+        let parsed: super::Crane = crane.to_string().parse()?;
+        assert_eq!(parsed.boxes, crane.boxes);
+
+        crane.simulate(&super::CrateMover9001)?;
+        assert_eq!(view_stack(&crane, &1), "['J', 'K', 'AB', 'C']");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bad_diagram() {
+        assert!("[A] [B\n 1   2\n".parse::<super::Crane>().is_err());
+        assert!("[A] [B]\n".parse::<super::Crane>().is_err());
+        assert!("[A][B]\n 1 \n".parse::<super::Crane>().is_err());
+
+        // Crates must sit over a column number, not past the last one or
+        // midway between two
+        let error = |input: &str| input.parse::<super::Crane>().unwrap_err().to_string();
+        assert_eq!(
+            error("[A] [B]     [D]\n 1   2   3\n"),
+            "line 1: crate 'D' doesn't line up with a column number"
+        );
+        assert_eq!(
+            error("[A]   [B]\n 1   2   3\n"),
+            "line 1: crate 'B' doesn't line up with a column number"
+        );
+        assert!("        [A]\n 1 \n".parse::<super::Crane>().is_err());
+        assert!(" [A]\n 1   2\n".parse::<super::Crane>().is_ok());
+    }
+
+    #[test]
+    fn test_save_and_load() -> Result<()> {
+        let dir = std::env::temp_dir();
+        let crane: super::Crane = std::fs::read_to_string("inputs/day5.test")?.parse()?;
+        let mut debugger = super::Debugger::new(crane, &super::CrateMover9001)?;
+        debugger.run(2);
+
+        for name in ["day5_checkpoint.json", "day5_checkpoint.toml"] {
+            let path = dir.join(name);
+            debugger.crane.save_to(&path)?;
+
+            let mut loaded = super::Crane::load_from(&path)?;
+            std::fs::remove_file(&path)?;
+
+            assert_eq!(loaded.boxes, debugger.crane.boxes);
+            assert_eq!(loaded.instructions.len(), 2);
+
+            loaded.simulate(&super::CrateMover9001)?;
+            assert_eq!(loaded.top_crates(), "MCD");
+        }
+
+        assert!(debugger.crane.save_to(&dir.join("day5.yaml")).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_load_handwritten() -> Result<()> {
+        let path = std::env::temp_dir().join("day5_handwritten.toml");
+        std::fs::write(
+            &path,
+            concat!(
+                "stacks = [[\"Z\", \"N\"], [], [\"P\"]]\n",
+                "\n",
+                "[[instructions]]\n",
+                "amount = 2\n",
+                "from = 1\n",
+                "to = 2\n",
+            ),
+        )?;
+
+        let mut crane = super::Crane::load_from(&path)?;
+        std::fs::remove_file(&path)?;
+
+        crane.simulate(&super::CrateMover9000)?;
+        assert_eq!(view_stack(&crane, &2), "['Z', 'N']");
+        assert_eq!(crane.top_crates(), " ZP");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reverse() -> Result<()> {
+        let models: [&dyn super::CraneModel; 3] = [
+            &super::CrateMover9000,
+            &super::CrateMover9001,
+            &super::CrateMoverUpTo(2),
+        ];
+
+        for path in ["inputs/day5.test", "inputs/day5.prod"] {
+            let original: super::Crane = std::fs::read_to_string(path)?.parse()?;
+
+            for model in models {
+                let mut crane = original.clone();
+                crane.simulate(model)?;
+                crane.instructions = original.instructions.clone();
+
+                crane.reverse(model)?;
+                assert_eq!(crane.boxes, original.boxes);
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reverse_impossible() -> Result<()> {
+        let mut crane: super::Crane = std::fs::read_to_string("inputs/day5.test")?.parse()?;
+
+        assert_eq!(
+            crane.reverse(&super::CrateMover9001),
+            Err(super::InvalidMove::NotEnoughCrates {
+                line: 7,
+                requested: 3,
+                available: 1
+            })
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_plan() -> Result<()> {
+        use super::Target;
+
+        let crane: super::Crane = std::fs::read_to_string("inputs/day5.test")?.parse()?;
+
+        let moves = crane
+            .plan(
+                &Target::TopCrates("CMZ".to_owned()),
+                &super::CrateMover9000,
+                4,
+            )
+            .unwrap();
+        assert_eq!(moves.len(), 2);
+
+        let mut planned = crane.clone();
+        planned.instructions = moves.into();
+        planned.simulate(&super::CrateMover9000)?;
+        assert_eq!(planned.top_crates(), "CMZ");
+
+        let mut expected = crane.clone();
+        expected.simulate(&super::CrateMover9001)?;
+        let target = Target::Stacks(expected.boxes.clone());
+
+        let model = super::parse_model("9001")?;
+        let moves = crane.plan(&target, model.as_ref(), 4).unwrap();
+        assert!(moves.len() <= 4);
+
+        let mut planned = crane.clone();
+        planned.instructions = moves.into();
+        planned.simulate(&super::CrateMover9001)?;
+        assert_eq!(planned.boxes, expected.boxes);
+
+        assert!(crane
+            .plan(
+                &Target::TopCrates("ZZZ".to_owned()),
+                &super::CrateMover9001,
+                3
+            )
+            .is_none());
+        assert_eq!(
+            crane
+                .plan(
+                    &Target::TopCrates("NDP".to_owned()),
+                    &super::CrateMover9001,
+                    0
+                )
+                .map(|moves| moves.len()),
+            Some(0)
+        );
+
+        let model = super::parse_model("upto:2")?;
+        assert_eq!(model.batch_size(5), 2);
+        assert!(crane
+            .plan(&Target::TopCrates("CMZ".to_owned()), model.as_ref(), 4)
+            .is_some());
+        assert_eq!(super::parse_model("9000")?.batch_size(5), 1);
+        assert!(super::parse_model("upto:0").is_err());
+        assert!(super::parse_model("9002").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_animate() -> Result<()> {
+        let mut crane: super::Crane = std::fs::read_to_string("inputs/day5.test")?.parse()?;
+        let mut animator = super::Animator {
+            out: Vec::new(),
+            delay: std::time::Duration::ZERO,
+        };
+
+        crane.simulate_with(&super::CrateMover9001, Some(&mut animator))?;
+
+        let output = String::from_utf8(animator.out)?;
+        let frames: Vec<_> = output.split("\x1b[2J\x1b[H").skip(1).collect();
+
+        assert_eq!(frames.len(), 8);
+        assert_eq!(
+            frames[0],
+            concat!(
+                "Step 1: move 1 from 2 to 1\n",
+                "\n",
+                "    \x1b[1;31m[D]\x1b[0m    \n",
+                "\x1b[36m[N]\x1b[0m \x1b[33m[C]\x1b[0m    \n",
+                "\x1b[36m[Z]\x1b[0m \x1b[33m[M]\x1b[0m [P]\n",
+                "\x1b[36m 1 \x1b[0m \x1b[33m 2 \x1b[0m  3 \n",
+            )
+        );
+        assert!(frames[1].starts_with("Step 1: move 1 from 2 to 1\n\n\x1b[1;31m[D]\x1b[0m"));
+
+        // Only crates that actually moved are in flight, wherever they land
+        let in_flight = |policy| -> Result<Vec<usize>> {
+            let mut crane: super::Crane = concat!(
+                "[C]            \n",
+                "[B] [Y] [W]    \n",
+                "[A] [X] [Z]    \n",
+                " 1   2   3   4 \n",
+                "\n",
+                "move 3 from 1 to 2\n",
+            )
+            .parse()?;
+            crane.capacity = Some(super::Capacity {
+                max_height: 3,
+                policy,
+            });
+            let mut animator = super::Animator {
+                out: Vec::new(),
+                delay: std::time::Duration::ZERO,
+            };
+
+            crane.simulate_with(&super::CrateMover9001, Some(&mut animator))?;
+
+            let output = String::from_utf8(animator.out)?;
+            Ok(output
+                .split("\x1b[2J\x1b[H")
+                .skip(1)
+                .map(|frame| frame.matches("\x1b[1;31m[").count())
+                .collect())
+        };
+
+        assert_eq!(in_flight(super::OverflowPolicy::Refuse)?, vec![0, 0]);
+        assert_eq!(in_flight(super::OverflowPolicy::Spill)?, vec![3, 3]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_statistics() -> Result<()> {
+        let mut crane: super::Crane = std::fs::read_to_string("inputs/day5.test")?.parse()?;
+        let mut stats = super::Statistics::new(&crane);
+
+        crane.simulate_with(&super::CrateMover9001, Some(&mut stats))?;
+
+        assert_eq!(stats.max_heights, vec![(3, 1), (3, 0), (4, 2)]);
+        assert_eq!(stats.moved_out, vec![4, 3, 0]);
+        assert_eq!(stats.moved_in, vec![3, 1, 3]);
+        assert_eq!(stats.moves_per_crate["D"], 2);
+        assert_eq!(stats.moves_per_crate["C"], 2);
+        assert_eq!(stats.moves_per_crate["N"], 1);
+        assert!(!stats.moves_per_crate.contains_key("P"));
+        assert_eq!(stats.path("D"), Some(&[2, 1, 3][..]));
+        assert_eq!(stats.path("C"), Some(&[2, 1, 2][..]));
+        assert_eq!(stats.path("P"), Some(&[3][..]));
+        assert_eq!(stats.path("X"), None);
+
+        // Crates that spill past a full target are followed to where they land
+        let mut crane: super::Crane = concat!(
+            "[C]            \n",
+            "[B] [Y] [W]    \n",
+            "[A] [X] [Z]    \n",
+            " 1   2   3   4 \n",
+            "\n",
+            "move 3 from 1 to 2\n",
+        )
+        .parse()?;
+        crane.capacity = Some(super::Capacity {
+            max_height: 3,
+            policy: super::OverflowPolicy::Spill,
+        });
+        let mut stats = super::Statistics::new(&crane);
+
+        crane.simulate_with(&super::CrateMover9001, Some(&mut stats))?;
+
+        assert_eq!(stats.moved_out, vec![3, 0, 0, 0]);
+        assert_eq!(stats.moved_in, vec![0, 1, 1, 1]);
+        assert_eq!(stats.max_heights, vec![(3, 0), (3, 1), (3, 1), (1, 1)]);
+        assert_eq!(stats.path("A"), Some(&[1, 2][..]));
+        assert_eq!(stats.path("B"), Some(&[1, 3][..]));
+        assert_eq!(stats.path("C"), Some(&[1, 4][..]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_move_syntax() -> Result<()> {
+        let input = concat!(
+            "    [D]    \n",
+            "[N] [C]    \n",
+            "[Z] [M] [P]\n",
+            " 1   2   3 \n",
+            "\n",
+            "# the example, spelled a few different ways\n",
+            "move 1 from 2 to 1\n",
+            "3 1->3   # shorthand\n",
+            "  move  2 from 2 to 1  \n",
+            "1 1 -> 2\n",
+        );
+        let mut crane: super::Crane = input.parse()?;
+
+        assert_eq!(crane.instructions.len(), 4);
+        assert_eq!(crane.instructions[1].line, 8);
+
+        crane.simulate(&super::CrateMover9000)?;
+        assert_eq!(crane.top_crates(), "CMZ");
+
+        let err = format!("{}movee 1 from 1 to 2\n", input)
+            .parse::<super::Crane>()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "line 11: unrecognised instruction 'movee 1 from 1 to 2'"
+        );
+
+        assert!(format!("{}[Q]\n", input).parse::<super::Crane>().is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_capacity() -> Result<()> {
+        use super::{Capacity, InvalidMove, Overflow, OverflowPolicy};
+
+        let original: super::Crane = std::fs::read_to_string("inputs/day5.test")?.parse()?;
+        let with_policy = |policy| {
+            let mut crane = original.clone();
+            crane.capacity = Some(Capacity {
+                max_height: 3,
+                policy,
+            });
+            crane
+        };
+
+        let mut crane = with_policy(OverflowPolicy::Error);
+        assert_eq!(
+            crane.validate(),
+            Err(InvalidMove::Overflow {
+                line: 7,
+                column: 3,
+                capacity: 3
+            })
+        );
+        assert!(crane.simulate(&super::CrateMover9000).is_err());
+
+        let mut crane = original.clone();
+        crane.capacity = Some(Capacity {
+            max_height: 2,
+            policy: OverflowPolicy::Spill,
+        });
+        assert_eq!(
+            crane.validate(),
+            Err(InvalidMove::OverCapacity {
+                column: 2,
+                height: 3,
+                capacity: 2
+            })
+        );
+
+        let mut crane = with_policy(OverflowPolicy::Refuse);
+        assert_eq!(
+            crane.simulate(&super::CrateMover9000)?,
+            vec![Overflow::Refused { line: 7 }, Overflow::Refused { line: 8 }]
+        );
+        assert_eq!(crane.top_crates(), "NDP");
+
+        let mut crane = with_policy(OverflowPolicy::Spill);
+        assert_eq!(
+            crane.simulate(&super::CrateMover9000)?,
+            vec![Overflow::Spilled {
+                line: 7,
+                spills: vec![(2, 1)]
+            }]
+        );
+        assert_eq!(view_stack(&crane, &1), "['Z']");
+        assert_eq!(view_stack(&crane, &2), "['C', 'M']");
+        assert_eq!(view_stack(&crane, &3), "['N', 'D', 'P']");
+
+        for policy in [OverflowPolicy::Refuse, OverflowPolicy::Spill] {
+            let crane = with_policy(policy);
+            let mut debugger = super::Debugger::new(crane.clone(), &super::CrateMover9000)?;
+
+            debugger.run(4);
+            debugger.goto(0);
+            assert_eq!(debugger.crane.boxes, crane.boxes);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sparse_columns() -> Result<()> {
+        let crane: super::Crane = concat!(
+            "[D]            \n",
+            "[N] [C]     [P]\n",
+            " 1   2       4 \n",
+        )
+        .parse()?;
+
+        assert_eq!(crane.columns(), 4);
+        assert!(crane.stack(3).is_empty());
+        assert!(crane.stack(5).is_empty());
+        assert_eq!(crane.top_crates(), "DC P");
+        assert_eq!(crane.top_crates_with('.'), "DC.P");
+
+        let duplicated = " 1   2   2 \n".parse::<super::Crane>();
+        assert!(duplicated.is_err());
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod bench {
+    extern crate test;
+
+    use std::collections::{HashMap, VecDeque};
+
+    use test::Bencher;
+
+    use super::{Crane, CrateMover9001, Instruction, Label};
+
+    const COLUMNS: usize = 9;
+    const HEIGHT: usize = 2_000;
+    const MOVES: usize = 2_000;
+
+    /// A tall crane with a long list of valid moves, from a fixed-seed LCG.
+    fn generate() -> Crane {
+        let mut seed: u64 = 0x2022_0005;
+        let mut next = |bound: usize| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) as usize % bound
+        };
+
+        let mut crane = Crane::new();
+        crane.boxes = (0..COLUMNS)
+            .map(|col| {
+                (0..HEIGHT)
+                    .map(|i| ((b'A' + ((col + i) % 26) as u8) as char).to_string())
+                    .collect()
+            })
+            .collect();
+
+        let mut heights = [HEIGHT; COLUMNS];
+
+        for line in 0..MOVES {
+            let from = next(COLUMNS);
+            let to = (from + 1 + next(COLUMNS - 1)) % COLUMNS;
+            let amount = 1 + next(heights[from].min(20));
+
+            heights[from] -= amount;
+            heights[to] += amount;
+
+            crane.instructions.push_back(Instruction {
+                amount,
+                from: from + 1,
+                to: to + 1,
+                line,
+            });
+        }
+
+        crane
+    }
+
+    /// How the stacks used to be stored: a map of deques with the top crate
+    /// at the front, so every move rebuilt the whole target column.
+    fn legacy_boxes(crane: &Crane) -> HashMap<usize, VecDeque<Label>> {
+        crane
+            .boxes
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (i + 1, v.iter().rev().cloned().collect()))
+            .collect()
+    }
+
+    fn legacy_simulate(boxes: &mut HashMap<usize, VecDeque<Label>>, crane: &Crane) {
+        for instruction in &crane.instructions {
+            let current_col = boxes.get_mut(&instruction.from).unwrap();
+            let mut tmp: VecDeque<_> = current_col.drain(..instruction.amount).collect();
+
+            tmp.append(boxes.get_mut(&instruction.to).unwrap());
+            boxes.insert(instruction.to, tmp);
+        }
+    }
+
+    #[test]
+    fn test_legacy_agrees() {
+        let mut crane = generate();
+        let mut boxes = legacy_boxes(&crane);
+
+        legacy_simulate(&mut boxes, &crane);
+        crane.simulate(&CrateMover9001).unwrap();
+
+        let legacy_tops: String = (1..=COLUMNS)
+            .map(|i| boxes[&i].front().map_or(" ", String::as_str))
+            .collect();
+
+        assert_eq!(crane.top_crates(), legacy_tops);
+    }
+
+    #[bench]
+    fn bench_simulate(b: &mut Bencher) {
+        let crane = generate();
+
+        b.iter(|| {
+            let mut crane = crane.clone();
+            crane.simulate(&CrateMover9001).unwrap();
+            crane.top_crates()
+        });
+    }
+
+    #[bench]
+    fn bench_legacy_simulate(b: &mut Bencher) {
+        let crane = generate();
+        let boxes = legacy_boxes(&crane);
+
+        b.iter(|| {
+            let mut boxes = boxes.clone();
+            legacy_simulate(&mut boxes, &crane);
+            boxes.len()
+        });
+    }
 }
@@ -1,44 +1,602 @@
-use std::collections::HashSet;
+#![cfg_attr(test, feature(test))]
 
-use anyhow::Result;
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Read, Write};
+use std::ops::Range;
 
-fn get_start(line: &str, size: usize) -> usize {
-    line.as_bytes()
-        .windows(size)
-        .position(|set| {
-            let mut data = 0;
+use anyhow::{Context, Result};
 
-            for &c in set {
-                let prev: usize = data;
-                // Bit masking to see if character already exists
-                data |= 1 << (c - b'a');
+/// The bytes a stream may contain, each with a slot of its own to count in.
+#[derive(Debug, Clone)]
+struct Alphabet {
+    slots: [Option<u8>; 256],
+    len: usize,
+}
+
+impl Alphabet {
+    fn new(bytes: impl IntoIterator<Item = u8>) -> Self {
+        let mut slots = [None; 256];
+        let mut len = 0;
+
+        for byte in bytes {
+            if slots[byte as usize].is_none() {
+                slots[byte as usize] = Some(len as u8);
+                len += 1;
+            }
+        }
+
+        Self { slots, len }
+    }
+
+    /// `a` to `z`, as the puzzle streams use.
+    fn lowercase() -> Self {
+        Self::new(b'a'..=b'z')
+    }
+
+    /// Every byte there is.
+    fn bytes() -> Self {
+        Self::new(0..=u8::MAX)
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn slot(&self, byte: u8) -> Option<usize> {
+        self.slots[byte as usize].map(usize::from)
+    }
+}
+
+/// A byte turned up in a stream that its alphabet doesn't have.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct UnsupportedByte {
+    offset: usize,
+    byte: u8,
+}
+
+impl std::fmt::Display for UnsupportedByte {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "byte {:#04x} at offset {} is not in the alphabet",
+            self.byte, self.offset
+        )
+    }
+}
+
+impl std::error::Error for UnsupportedByte {}
+
+/// Looks for the first `size` consecutive distinct bytes of an alphabet.
+#[derive(Debug, Clone)]
+struct MarkerFinder {
+    alphabet: Alphabet,
+    size: usize,
+}
+
+impl MarkerFinder {
+    fn new(alphabet: Alphabet, size: usize) -> Self {
+        Self { alphabet, size }
+    }
+
+    /// Whether a window of this size can hold a marker at all: more bytes
+    /// than the alphabet has can never all differ.
+    fn possible(&self) -> bool {
+        self.size > 0 && self.size <= self.alphabet.len()
+    }
+
+    /// Position just past the marker, or `None` if the stream has none.
+    /// Bytes past the marker aren't checked against the alphabet.
+    fn find(&self, bytes: &[u8]) -> Result<Option<usize>, UnsupportedByte> {
+        self.markers(bytes).next().transpose()
+    }
+
+    /// Every position a marker ends at, overlapping ones included.
+    fn find_all(&self, bytes: &[u8]) -> Result<Vec<usize>, UnsupportedByte> {
+        self.markers(bytes).collect()
+    }
+
+    fn markers<'a>(&'a self, bytes: &'a [u8]) -> Markers<'a> {
+        Markers {
+            finder: self,
+            bytes,
+            counts: Counts::new(&self.alphabet),
+            next: if self.possible() { 0 } else { bytes.len() },
+        }
+    }
+
+    fn window(&self) -> Window {
+        Window {
+            finder: self.clone(),
+            slots: VecDeque::with_capacity(self.size),
+            counts: Counts::new(&self.alphabet),
+            seen: 0,
+        }
+    }
+}
+
+/// Walks a window over `bytes`, yielding the end of every marker in turn.
+struct Markers<'a> {
+    finder: &'a MarkerFinder,
+    bytes: &'a [u8],
+    counts: Counts,
+    /// The next byte to enter the window.
+    next: usize,
+}
+
+impl Markers<'_> {
+    fn slot(&self, offset: usize) -> Result<usize, UnsupportedByte> {
+        let byte = self.bytes[offset];
+        self.finder
+            .alphabet
+            .slot(byte)
+            .ok_or(UnsupportedByte { offset, byte })
+    }
+
+    fn advance(&mut self) -> Result<bool, UnsupportedByte> {
+        let i = self.next;
+        let size = self.finder.size;
+        self.next += 1;
+
+        self.counts.enter(self.slot(i)?);
+
+        if i >= size {
+            self.counts.leave(self.slot(i - size)?);
+        }
+
+        Ok(i + 1 >= size && self.counts.distinct())
+    }
+}
+
+impl Iterator for Markers<'_> {
+    type Item = Result<usize, UnsupportedByte>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.next < self.bytes.len() {
+            match self.advance() {
+                Ok(true) => return Some(Ok(self.next)),
+                Ok(false) => {}
+                Err(e) => {
+                    self.next = self.bytes.len();
+                    return Some(Err(e));
+                }
+            }
+        }
+
+        None
+    }
+}
+
+/// The longest stretch of `bytes` without a repeat, as the range it covers.
+/// The first one wins a tie; an empty stream gives an empty range.
+fn longest_distinct_run(
+    bytes: &[u8],
+    alphabet: &Alphabet,
+) -> Result<Range<usize>, UnsupportedByte> {
+    // Where every byte of the alphabet was last seen
+    let mut last_seen = vec![None; alphabet.len()];
+    let mut longest = 0..0;
+    let mut start = 0;
+
+    for (offset, &byte) in bytes.iter().enumerate() {
+        let slot = alphabet
+            .slot(byte)
+            .ok_or(UnsupportedByte { offset, byte })?;
+
+        if let Some(last) = last_seen[slot] {
+            start = start.max(last + 1);
+        }
+
+        last_seen[slot] = Some(offset);
+
+        if offset + 1 - start > longest.len() {
+            longest = start..offset + 1;
+        }
+    }
+
+    Ok(longest)
+}
+
+/// How often every byte of an alphabet occurs in a window, and how many of
+/// them occur more than once, so that a byte entering or leaving the window
+/// is all it takes to tell whether the rest are distinct.
+#[derive(Debug, Clone)]
+struct Counts {
+    counts: Vec<usize>,
+    repeated: usize,
+}
+
+impl Counts {
+    fn new(alphabet: &Alphabet) -> Self {
+        Self {
+            counts: vec![0; alphabet.len()],
+            repeated: 0,
+        }
+    }
+
+    fn enter(&mut self, slot: usize) {
+        self.counts[slot] += 1;
+
+        if self.counts[slot] == 2 {
+            self.repeated += 1;
+        }
+    }
+
+    fn leave(&mut self, slot: usize) {
+        if self.counts[slot] == 2 {
+            self.repeated -= 1;
+        }
+
+        self.counts[slot] -= 1;
+    }
+
+    fn distinct(&self) -> bool {
+        self.repeated == 0
+    }
+}
+
+/// A marker-sized window sliding over a stream a byte at a time, for when
+/// the bytes don't stay around to be looked at again as they leave.
+#[derive(Debug, Clone)]
+struct Window {
+    finder: MarkerFinder,
+    /// Alphabet slots of the bytes in the window, oldest first.
+    slots: VecDeque<usize>,
+    counts: Counts,
+    /// How many bytes have gone in so far.
+    seen: usize,
+}
+
+impl Window {
+    /// Take in the next byte, returning whether the window now holds a
+    /// marker.
+    fn push(&mut self, byte: u8) -> Result<bool, UnsupportedByte> {
+        let slot = self.finder.alphabet.slot(byte).ok_or(UnsupportedByte {
+            offset: self.seen,
+            byte,
+        })?;
+        self.seen += 1;
+
+        if !self.finder.possible() {
+            return Ok(false);
+        }
+
+        self.counts.enter(slot);
+        self.slots.push_back(slot);
+
+        if self.slots.len() > self.finder.size {
+            if let Some(leaving) = self.slots.pop_front() {
+                self.counts.leave(leaving);
+            }
+        }
+
+        Ok(self.slots.len() == self.finder.size && self.counts.distinct())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MarkerKind {
+    StartOfPacket,
+    StartOfMessage,
+}
+
+/// Where a marker ended: `offset` bytes into datastream number `stream`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Marker {
+    stream: usize,
+    kind: MarkerKind,
+    offset: usize,
+}
+
+const BUFFER_SIZE: usize = 4096;
+
+/// Reports markers from a reader as soon as they come in, reading it in
+/// fixed-size chunks so that it never has to end. Every line is a datastream
+/// of its own.
+struct MarkerStream<R> {
+    reader: R,
+    buffer: Box<[u8; BUFFER_SIZE]>,
+    filled: usize,
+    position: usize,
+    stream: usize,
+    /// The windows still looking for their marker in the current datastream.
+    windows: Vec<(MarkerKind, Window)>,
+    finders: [(MarkerKind, MarkerFinder); 2],
+    found: VecDeque<Marker>,
+}
+
+impl<R: Read> MarkerStream<R> {
+    fn new(reader: R, alphabet: Alphabet) -> Self {
+        let finders = [
+            (
+                MarkerKind::StartOfPacket,
+                MarkerFinder::new(alphabet.clone(), 4),
+            ),
+            (MarkerKind::StartOfMessage, MarkerFinder::new(alphabet, 14)),
+        ];
+
+        let mut stream = Self {
+            reader,
+            buffer: Box::new([0; BUFFER_SIZE]),
+            filled: 0,
+            position: 0,
+            stream: 0,
+            windows: Vec::new(),
+            finders,
+            found: VecDeque::new(),
+        };
+        stream.next_stream();
 
-                if prev == data {
-                    return false;
+        stream
+    }
+
+    fn next_stream(&mut self) {
+        self.windows = self
+            .finders
+            .iter()
+            .map(|(kind, finder)| (*kind, finder.window()))
+            .collect();
+    }
+
+    /// The next byte of the reader, `None` once it runs dry.
+    fn next_byte(&mut self) -> Result<Option<u8>> {
+        while self.position == self.filled {
+            match self.reader.read(&mut self.buffer[..]) {
+                Ok(0) => return Ok(None),
+                Ok(read) => {
+                    self.filled = read;
+                    self.position = 0;
                 }
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
             }
+        }
 
-            true
-        })
-        .map(|i| i + size)
-        .unwrap()
+        self.position += 1;
+        Ok(Some(self.buffer[self.position - 1]))
+    }
 }
 
-fn get_stream_start(line: &str) -> usize {
+impl<R: Read> Iterator for MarkerStream<R> {
+    type Item = Result<Marker>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.found.is_empty() {
+            let byte = match self.next_byte() {
+                Ok(Some(byte)) => byte,
+                Ok(None) => return None,
+                Err(e) => return Some(Err(e)),
+            };
+
+            if byte == b'\n' {
+                self.stream += 1;
+                self.next_stream();
+                continue;
+            }
+
+            let mut i = 0;
+
+            while i < self.windows.len() {
+                let (kind, window) = &mut self.windows[i];
+
+                match window.push(byte) {
+                    Ok(true) => {
+                        self.found.push_back(Marker {
+                            stream: self.stream,
+                            kind: *kind,
+                            offset: window.seen,
+                        });
+                        self.windows.remove(i);
+                    }
+                    Ok(false) => i += 1,
+                    Err(e) => {
+                        // Carry on with the next line after a bad byte
+                        self.windows.clear();
+                        let stream = self.stream;
+                        return Some(Err(e).with_context(|| format!("stream {}", stream)));
+                    }
+                }
+            }
+        }
+
+        self.found.pop_front().map(Ok)
+    }
+}
+
+/// A message pulled out of a datastream: the start-of-message marker, and
+/// everything after it up to the next one. `offset` is where the payload
+/// begins, counted the way the puzzle counts marker positions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Frame<'a> {
+    offset: usize,
+    marker: &'a [u8],
+    payload: &'a [u8],
+}
+
+/// Splits a datastream into successive [`Frame`]s. Anything ahead of the
+/// first marker is noise and gets skipped.
+struct Frames<'a> {
+    finder: MarkerFinder,
+    bytes: &'a [u8],
+    /// Where the next marker ends, if the last payload was cut short by it.
+    next_marker: Option<usize>,
+    done: bool,
+}
+
+impl<'a> Frames<'a> {
+    fn new(bytes: &'a [u8], alphabet: Alphabet) -> Self {
+        Self {
+            finder: MarkerFinder::new(alphabet, 14),
+            bytes,
+            next_marker: None,
+            done: false,
+        }
+    }
+
+    /// Where the first marker at or after `start` ends.
+    fn find_from(&self, start: usize) -> Result<Option<usize>, UnsupportedByte> {
+        match self.finder.find(&self.bytes[start..]) {
+            Ok(end) => Ok(end.map(|end| start + end)),
+            Err(e) => Err(UnsupportedByte {
+                offset: start + e.offset,
+                ..e
+            }),
+        }
+    }
+
+    fn next_frame(&mut self) -> Result<Option<Frame<'a>>, UnsupportedByte> {
+        let marker_end = match self.next_marker.take() {
+            Some(end) => end,
+            None => match self.find_from(0)? {
+                Some(end) => end,
+                None => return Ok(None),
+            },
+        };
+
+        let payload_end = match self.find_from(marker_end)? {
+            Some(end) => {
+                self.next_marker = Some(end);
+                end - self.finder.size
+            }
+            None => {
+                self.done = true;
+                self.bytes.len()
+            }
+        };
+
+        Ok(Some(Frame {
+            offset: marker_end,
+            marker: &self.bytes[marker_end - self.finder.size..marker_end],
+            payload: &self.bytes[marker_end..payload_end],
+        }))
+    }
+}
+
+impl<'a> Iterator for Frames<'a> {
+    type Item = Result<Frame<'a>, UnsupportedByte>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        let frame = self.next_frame();
+
+        if !matches!(frame, Ok(Some(_))) {
+            self.done = true;
+        }
+
+        frame.transpose()
+    }
+}
+
+fn get_start(line: &str, size: usize) -> Result<Option<usize>, UnsupportedByte> {
+    MarkerFinder::new(Alphabet::lowercase(), size).find(line.as_bytes())
+}
+
+fn get_stream_start(line: &str) -> Result<Option<usize>, UnsupportedByte> {
     get_start(line, 4)
 }
 
-fn get_message_start(line: &str) -> usize {
+fn get_message_start(line: &str) -> Result<Option<usize>, UnsupportedByte> {
     get_start(line, 14)
 }
 
 fn main() -> Result<()> {
+    if std::env::args().any(|arg| arg == "--stream") {
+        let mut stdout = std::io::stdout();
+
+        for marker in MarkerStream::new(std::io::stdin().lock(), Alphabet::lowercase()) {
+            match marker {
+                Ok(Marker {
+                    stream,
+                    kind,
+                    offset,
+                }) => {
+                    writeln!(stdout, "stream {}: {:?} at {}", stream, kind, offset)?;
+                    stdout.flush()?;
+                }
+                // A bad byte only spoils its own line; the stream goes on
+                Err(e) if e.is::<UnsupportedByte>() => eprintln!("{:#}", e),
+                Err(e) => return Err(e),
+            }
+        }
+
+        return Ok(());
+    }
+
     let streams = std::fs::read_to_string("inputs/day6.prod")?;
 
-    let stream_starts: Vec<_> = streams.lines().map(get_stream_start).collect();
+    if let Some(size) = std::env::args()
+        .skip_while(|arg| arg != "--any-byte")
+        .nth(1)
+    {
+        let finder = MarkerFinder::new(Alphabet::bytes(), size.parse()?);
+        let starts = streams
+            .lines()
+            .map(|line| finder.find(line.as_bytes()))
+            .collect::<Result<Vec<_>, _>>()?;
+        println!("Start of {}-byte marker: {:?}", size, starts);
+
+        return Ok(());
+    }
+
+    if let Some(size) = std::env::args().skip_while(|arg| arg != "--all").nth(1) {
+        let finder = MarkerFinder::new(Alphabet::lowercase(), size.parse()?);
+
+        for (i, line) in streams.lines().enumerate() {
+            let positions = finder.find_all(line.as_bytes())?;
+            println!(
+                "stream {}: {} markers at {:?}",
+                i,
+                positions.len(),
+                positions
+            );
+        }
+
+        return Ok(());
+    }
+
+    if std::env::args().any(|arg| arg == "--longest") {
+        for (i, line) in streams.lines().enumerate() {
+            let run = longest_distinct_run(line.as_bytes(), &Alphabet::lowercase())?;
+            println!("stream {}: '{}' at {:?}", i, &line[run.clone()], run);
+        }
+
+        return Ok(());
+    }
+
+    if std::env::args().any(|arg| arg == "--frames") {
+        for (i, line) in streams.lines().enumerate() {
+            for frame in Frames::new(line.as_bytes(), Alphabet::lowercase()) {
+                let Frame {
+                    offset,
+                    marker,
+                    payload,
+                } = frame?;
+
+                println!(
+                    "stream {} at {}: [{}] {}",
+                    i,
+                    offset,
+                    String::from_utf8_lossy(marker),
+                    String::from_utf8_lossy(payload)
+                );
+            }
+        }
+
+        return Ok(());
+    }
+
+    let stream_starts = streams
+        .lines()
+        .map(get_stream_start)
+        .collect::<Result<Vec<_>, _>>()?;
     println!("Start part 1: {:?}", stream_starts);
 
-    let message_starts: Vec<_> = streams.lines().map(get_message_start).collect();
+    let message_starts = streams
+        .lines()
+        .map(get_message_start)
+        .collect::<Result<Vec<_>, _>>()?;
     println!("Start part 2: {:?}", message_starts);
 
     Ok(())
@@ -46,7 +604,12 @@ fn main() -> Result<()> {
 
 #[cfg(test)]
 mod tests {
-    use crate::{get_message_start, get_stream_start};
+    use std::io::Read;
+
+    use crate::{
+        get_message_start, get_stream_start, longest_distinct_run, Alphabet, Frame, Frames, Marker,
+        MarkerFinder, MarkerKind, MarkerStream, UnsupportedByte,
+    };
 
     use anyhow::Result;
 
@@ -54,9 +617,12 @@ mod tests {
     fn test_one() -> Result<()> {
         let streams = std::fs::read_to_string("inputs/day6.test")?;
 
-        let starts: Vec<_> = streams.lines().map(get_stream_start).collect();
+        let starts = streams
+            .lines()
+            .map(get_stream_start)
+            .collect::<Result<Vec<_>, _>>()?;
 
-        assert_eq!(starts, vec![7, 5, 6, 10, 11]);
+        assert_eq!(starts, vec![Some(7), Some(5), Some(6), Some(10), Some(11)]);
         Ok(())
     }
 
@@ -64,9 +630,332 @@ mod tests {
     fn test_two() -> Result<()> {
         let streams = std::fs::read_to_string("inputs/day6.test")?;
 
-        let starts: Vec<_> = streams.lines().map(get_message_start).collect();
+        let starts = streams
+            .lines()
+            .map(get_message_start)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        assert_eq!(
+            starts,
+            vec![Some(19), Some(23), Some(23), Some(29), Some(26)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_window_sizes() -> Result<()> {
+        let find = |bytes: &[u8], size| MarkerFinder::new(Alphabet::lowercase(), size).find(bytes);
+        let alphabet = b"zzabcdefghijklmnopqrstuvwxyz";
+
+        assert_eq!(find(alphabet, 26)?, Some(27));
+        assert_eq!(find(alphabet, 27)?, None);
+        assert_eq!(find(alphabet, 0)?, None);
+        assert_eq!(find(b"aaa", 1)?, Some(1));
+        assert_eq!(find(b"abab", 3)?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_alphabets() -> Result<()> {
+        assert_eq!(
+            get_stream_start("abcABCD"),
+            Err(UnsupportedByte {
+                offset: 3,
+                byte: b'A'
+            })
+        );
+        // Bytes after the marker are never looked at
+        assert_eq!(get_stream_start("abcdABCD")?, Some(4));
+
+        let bytes = MarkerFinder::new(Alphabet::bytes(), 4);
+        assert_eq!(bytes.find(b"aaA1\x00\xff")?, Some(5));
+        assert_eq!(bytes.find(b"\x00\x00\x00")?, None);
 
-        assert_eq!(starts, vec![19, 23, 23, 29, 26]);
+        let digits = MarkerFinder::new(Alphabet::new(b'0'..=b'9'), 10);
+        assert_eq!(digits.find(b"00123456789")?, Some(11));
+        assert_eq!(
+            MarkerFinder::new(Alphabet::bytes(), 256).find(&[0; 300])?,
+            None
+        );
         Ok(())
     }
+
+    /// Hands out a few bytes per read, like a slow pipe.
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = self.0.len().min(buf.len()).min(3);
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn test_stream() -> Result<()> {
+        let streams = std::fs::read_to_string("inputs/day6.test")?;
+
+        let markers = MarkerStream::new(Trickle(streams.as_bytes()), Alphabet::lowercase())
+            .collect::<Result<Vec<_>>>()?;
+        let offsets = |kind| {
+            markers
+                .iter()
+                .filter(|m| m.kind == kind)
+                .map(|m| (m.stream, m.offset))
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            offsets(MarkerKind::StartOfPacket),
+            vec![(0, 7), (1, 5), (2, 6), (3, 10), (4, 11)]
+        );
+        assert_eq!(
+            offsets(MarkerKind::StartOfMessage),
+            vec![(0, 19), (1, 23), (2, 23), (3, 29), (4, 26)]
+        );
+
+        // Markers turn up without waiting for an endless stream to finish
+        let endless = b"abcdefghijklmn".chain(std::io::repeat(b'a'));
+        let mut markers = MarkerStream::new(endless, Alphabet::lowercase());
+        assert_eq!(
+            markers.next().transpose()?,
+            Some(Marker {
+                stream: 0,
+                kind: MarkerKind::StartOfPacket,
+                offset: 4
+            })
+        );
+        assert_eq!(markers.next().transpose()?.map(|m| m.offset), Some(14));
+
+        let mut markers = MarkerStream::new(&b"abA\nabcd"[..], Alphabet::lowercase());
+        let error = markers.next().unwrap().unwrap_err();
+        assert!(error.is::<UnsupportedByte>());
+        assert_eq!(
+            format!("{:#}", error),
+            "stream 0: byte 0x41 at offset 2 is not in the alphabet"
+        );
+        assert_eq!(
+            markers.next().transpose()?.map(|m| (m.stream, m.offset)),
+            Some((1, 4))
+        );
+        assert!(markers.next().is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_frames() -> Result<()> {
+        let streams = std::fs::read_to_string("inputs/day6.test")?;
+
+        let frames = streams
+            .lines()
+            .map(|line| Frames::new(line.as_bytes(), Alphabet::lowercase()).collect())
+            .collect::<Result<Vec<Vec<_>>, _>>()?;
+
+        // None of the test streams is long enough for a second message
+        let offsets: Vec<_> = frames
+            .iter()
+            .map(|frames| frames.iter().map(|f| f.offset).collect::<Vec<_>>())
+            .collect();
+        assert_eq!(offsets, vec![[19], [23], [23], [29], [26]]);
+
+        assert_eq!(
+            frames[0][0],
+            Frame {
+                offset: 19,
+                marker: b"qmgbljsphdztnv",
+                payload: b"jfqwrcgsmlb",
+            }
+        );
+        assert_eq!(frames[3][0].payload, b"prsg");
+
+        let frames = Frames::new(
+            b"aaabcdefghijklmnzzoopqrstuvwxyabczz",
+            Alphabet::lowercase(),
+        )
+        .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(
+            frames,
+            vec![
+                Frame {
+                    offset: 16,
+                    marker: b"abcdefghijklmn",
+                    payload: b"zzo",
+                },
+                Frame {
+                    offset: 33,
+                    marker: b"opqrstuvwxyabc",
+                    payload: b"zz",
+                },
+            ]
+        );
+
+        let mut frames = Frames::new(b"abcdefghijklmnopQ", Alphabet::lowercase());
+        // A bad byte in the payload spoils the whole frame
+        assert_eq!(
+            frames.next(),
+            Some(Err(UnsupportedByte {
+                offset: 16,
+                byte: b'Q'
+            }))
+        );
+        assert_eq!(frames.next(), None);
+
+        Ok(())
+    }
+
+    fn distinct(window: &[u8]) -> bool {
+        (1..window.len()).all(|i| !window[..i].contains(&window[i]))
+    }
+
+    #[test]
+    fn test_find_all() -> Result<()> {
+        let streams = std::fs::read_to_string("inputs/day6.test")?;
+
+        for line in streams.lines().map(str::as_bytes) {
+            for size in 1..=15 {
+                let expected: Vec<_> = line
+                    .windows(size)
+                    .enumerate()
+                    .filter(|(_, window)| distinct(window))
+                    .map(|(i, _)| i + size)
+                    .collect();
+
+                let finder = MarkerFinder::new(Alphabet::lowercase(), size);
+                assert_eq!(finder.find_all(line)?, expected);
+            }
+        }
+
+        let finder = MarkerFinder::new(Alphabet::lowercase(), 4);
+        assert_eq!(finder.find_all(b"abcdeab")?, vec![4, 5, 6, 7]);
+        assert_eq!(finder.find_all(b"aaaa")?, vec![]);
+        assert!(finder.find_all(b"abcdA").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_longest_distinct_run() -> Result<()> {
+        let lowercase = Alphabet::lowercase();
+        let streams = std::fs::read_to_string("inputs/day6.test")?;
+
+        for line in streams.lines().map(str::as_bytes) {
+            let run = longest_distinct_run(line, &lowercase)?;
+            assert!(distinct(&line[run.clone()]));
+
+            // Nothing longer is distinct anywhere in the stream
+            assert!(line.windows(run.len() + 1).all(|w| !distinct(w)));
+        }
+
+        assert_eq!(longest_distinct_run(b"abcabcbb", &lowercase)?, 0..3);
+        assert_eq!(longest_distinct_run(b"pwwkew", &lowercase)?, 2..5);
+        assert_eq!(longest_distinct_run(b"bbbb", &lowercase)?, 0..1);
+        assert_eq!(longest_distinct_run(b"", &lowercase)?, 0..0);
+        assert_eq!(
+            longest_distinct_run(b"ab-", &lowercase),
+            Err(UnsupportedByte {
+                offset: 2,
+                byte: b'-'
+            })
+        );
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod bench {
+    extern crate test;
+
+    use test::Bencher;
+
+    use super::{Alphabet, MarkerFinder};
+
+    const LENGTH: usize = 100_000;
+
+    /// A long stream drawing on one letter fewer than `size`, from a
+    /// fixed-seed LCG, so the only marker is the one tacked on the end.
+    fn generate(size: usize) -> Vec<u8> {
+        let mut seed: u64 = 0x2022_0006;
+        let mut next = |bound: usize| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) as usize % bound
+        };
+
+        let mut stream: Vec<u8> = (0..LENGTH).map(|_| b'a' + next(size - 1) as u8).collect();
+        stream.extend((0..size as u8).map(|i| b'z' - i));
+
+        stream
+    }
+
+    /// The original check, rebuilding a bitmask for every window.
+    fn legacy_find_start(bytes: &[u8], size: usize) -> Option<usize> {
+        bytes
+            .windows(size)
+            .position(|set| {
+                let mut data = 0;
+
+                for &c in set {
+                    let prev: usize = data;
+                    data |= 1 << (c - b'a');
+
+                    if prev == data {
+                        return false;
+                    }
+                }
+
+                true
+            })
+            .map(|i| i + size)
+    }
+
+    fn find_start(bytes: &[u8], size: usize) -> Option<usize> {
+        MarkerFinder::new(Alphabet::lowercase(), size)
+            .find(bytes)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_legacy_agrees() -> anyhow::Result<()> {
+        let streams = std::fs::read_to_string("inputs/day6.prod")?;
+
+        for size in [4, 14] {
+            let stream = generate(size);
+            assert_eq!(find_start(&stream, size), legacy_find_start(&stream, size));
+
+            for line in streams.lines() {
+                let line = line.as_bytes();
+                assert_eq!(find_start(line, size), legacy_find_start(line, size));
+            }
+        }
+
+        Ok(())
+    }
+
+    #[bench]
+    fn bench_find_start_4(b: &mut Bencher) {
+        let stream = generate(4);
+        b.iter(|| find_start(&stream, 4));
+    }
+
+    #[bench]
+    fn bench_legacy_find_start_4(b: &mut Bencher) {
+        let stream = generate(4);
+        b.iter(|| legacy_find_start(&stream, 4));
+    }
+
+    #[bench]
+    fn bench_find_start_14(b: &mut Bencher) {
+        let stream = generate(14);
+        b.iter(|| find_start(&stream, 14));
+    }
+
+    #[bench]
+    fn bench_legacy_find_start_14(b: &mut Bencher) {
+        let stream = generate(14);
+        b.iter(|| legacy_find_start(&stream, 14));
+    }
 }